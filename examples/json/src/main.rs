@@ -1,24 +1,12 @@
 #[allow(unused_extern_crates)]
 extern crate serde;
-extern crate serde_json;
 extern crate shio;
 
-#[macro_use]
-extern crate error_chain;
-
 #[macro_use]
 extern crate serde_derive;
 
 use shio::prelude::*;
-
-mod errors {
-    error_chain! {
-        foreign_links {
-            Shio(::shio::Error);
-            Json(::serde_json::Error);
-        }
-    }
-}
+use shio::json::Json;
 
 #[derive(Debug, Deserialize)]
 struct RequestBody {
@@ -32,20 +20,19 @@ struct ResponseBody {
     name: String,
 }
 
-fn index(ctx: Context) -> BoxFuture<Response, errors::Error> {
-    // `concat2` will asynchronously read each chunk of the request body and
-    // return a single, concatenated, chunk
-    ctx.data().concat2()
-        // `Future::from_err` acts like `?` in that it coerces the error type from
-        // the future into the final error type
-        .from_err()
-        // `Future::and_then` can be used to merge an asynchronous workflow with a
+fn index(ctx: Context) -> BoxFuture<Response, shio::Error> {
+    // `Data::json` will asynchronously read each chunk of the request body and
+    // deserialize the concatenated body
+    ctx.data().json::<RequestBody>()
+        // `Future::then` can be used to merge an asynchronous workflow with a
         // synchronous workflow
-        .and_then(|data| /* -> errors::Result<Response> */ {
-            let body: RequestBody = serde_json::from_slice(&data)?;
-            let s = serde_json::to_string(&ResponseBody { id: 20, name: body.name })?;
+        .then(|body| match body {
+            // `Json` serializes the value and sets `Content-Type` and `Content-Length`;
+            // a value that fails to serialize results in a 500
+            Ok(body) => Response::with(Json(ResponseBody { id: 20, name: body.name })),
 
-            Ok(Response::build().header(http::header::ContentType::json()).body(s))
+            // A body that is too large or not valid JSON is the client's fault
+            Err(err) => Ok(Response::with(err.status())),
         })
         // Put our future inside a Box so we can name our return type
         // This part will go away once `impl Trait` is stablized in Rust
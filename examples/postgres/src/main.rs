@@ -4,6 +4,7 @@ extern crate pretty_env_logger;
 extern crate serde;
 extern crate serde_json;
 extern crate shio;
+extern crate tokio_core;
 extern crate tokio_postgres as postgres;
 extern crate uuid;
 
@@ -13,8 +14,6 @@ extern crate serde_derive;
 #[macro_use]
 extern crate error_chain;
 
-// TODO: Connection pooling
-
 mod errors {
     error_chain! {
         foreign_links {
@@ -28,6 +27,9 @@ mod errors {
 use futures_state_stream::StateStream;
 use postgres::{Connection, TlsMode};
 use shio::prelude::*;
+use shio::context::Key;
+use shio::pool::{self, Manager, Pool};
+use tokio_core::reactor::Handle;
 use errors::*;
 
 #[derive(Serialize)]
@@ -38,44 +40,84 @@ struct Person {
 
 const DATABASE_URL: &'static str = "postgres://postgres@localhost/shio_dev_example";
 
+struct PostgresManager;
+
+impl Manager for PostgresManager {
+    type Connection = Connection;
+    type Error = Error;
+
+    fn connect(&self, handle: &Handle) -> BoxFuture<Connection, Error> {
+        Connection::connect(DATABASE_URL, TlsMode::None, handle)
+            .from_err()
+            .into_box()
+    }
+
+    fn validate(&self, conn: Connection) -> BoxFuture<Connection, Error> {
+        conn.batch_execute("").from_err().into_box()
+    }
+}
+
+// Each worker thread has its own pool as connections are bound to the event loop
+// they were opened on
+struct Database;
+
+impl Key for Database {
+    type Value = Pool<PostgresManager>;
+}
+
 fn index(ctx: Context) -> BoxFuture<Response, Error> {
-    Connection::connect(DATABASE_URL, TlsMode::None, &ctx.handle())
-        .from_err::<Error>()
-        .and_then(|conn| {
-            conn.batch_execute(
-                r#"
-                CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
-                CREATE TABLE IF NOT EXISTS person (
-                    id UUID PRIMARY KEY DEFAULT uuid_generate_v1mc(),
-                    name TEXT NOT NULL
-                );
-            "#,
-            ).from_err()
-        })
-        .and_then(|conn| {
-            conn.prepare("INSERT INTO person (name) VALUES ($1)")
-                .from_err()
+    ctx.local()
+        .get::<Database>()
+        .get()
+        .map_err(|err| match err {
+            pool::Error::Manager(err) => err,
+            pool::Error::Timeout => "timed out waiting for a database connection".into(),
         })
-        .and_then(move |(stmt, conn)| {
-            let name: &str = &ctx.get::<Parameters>()["name"];
+        .and_then(move |mut pooled| {
+            // tokio-postgres consumes the connection for each query and hands it back
+            // when the query completes
+            pooled
+                .take()
+                .batch_execute(
+                    r#"
+                    CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+                    CREATE TABLE IF NOT EXISTS person (
+                        id UUID PRIMARY KEY DEFAULT uuid_generate_v1mc(),
+                        name TEXT NOT NULL
+                    );
+                "#,
+                )
+                .from_err::<Error>()
+                .and_then(|conn| {
+                    conn.prepare("INSERT INTO person (name) VALUES ($1)")
+                        .from_err()
+                })
+                .and_then(move |(stmt, conn)| {
+                    let name: &str = &ctx.get::<Parameters>()["name"];
 
-            conn.execute(&stmt, &[&name]).from_err()
-        })
-        .and_then(|(_, conn)| {
-            conn.prepare("SELECT id, name FROM person").from_err()
-        })
-        .and_then(|(stmt, conn)| {
-            conn.query(&stmt, &[])
-                .map(|row| {
-                    Person {
-                        id: row.get("id"),
-                        name: row.get("name"),
-                    }
+                    conn.execute(&stmt, &[&name]).from_err()
+                })
+                .and_then(|(_, conn)| {
+                    conn.prepare("SELECT id, name FROM person").from_err()
+                })
+                .and_then(|(stmt, conn)| {
+                    conn.query(&stmt, &[])
+                        .map(|row| {
+                            Person {
+                                id: row.get("id"),
+                                name: row.get("name"),
+                            }
+                        })
+                        .collect()
+                        .from_err()
+                })
+                .map(move |(results, conn)| {
+                    // Hand the connection back so it is returned to the pool
+                    pooled.put(conn);
+                    results
                 })
-                .collect()
-                .from_err()
         })
-        .and_then(|(results, _)| {
+        .and_then(|results| {
             let s = serde_json::to_string(&results)?;
 
             Ok(
@@ -90,7 +132,10 @@ fn index(ctx: Context) -> BoxFuture<Response, Error> {
 fn main() {
     pretty_env_logger::init().unwrap();
 
+    let config = pool::Builder::new().max_size(4);
+
     Shio::default()
+        .manage_local::<Database, _>(move |handle| config.build(PostgresManager, handle))
         .route((Method::GET, "/{name}", index))
         .run(":7878")
         .unwrap();
@@ -11,12 +11,11 @@ extern crate hyper;
 extern crate shio;
 
 use shio::prelude::*;
-use hyper::Client;
 
 fn proxy(ctx: Context) -> BoxFuture<Response, hyper::Error> {
-    // Additional work can be scheduled on the thread-local event loop,
-    // as each handler receives a reference to it
-    Client::new(ctx.handle())
+    // Each worker thread has a single outbound HTTP client that runs on its event loop
+    // and keeps connections to google alive between requests
+    ctx.client()
         .get("http://www.google.com".parse().unwrap())
         // Map the _streaming_ response from google into a _streaming_
         // response from us
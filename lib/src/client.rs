@@ -0,0 +1,338 @@
+//! An outbound HTTP client shared by all requests handled on a worker thread.
+//!
+//! Each worker thread lazily creates a single [`Client`] the first time
+//! [`Context::client`] is called, so connections to other services are kept alive and
+//! reused across requests. The client may be configured with [`Shio::client`].
+//!
+//! [`Client`]: struct.Client.html
+//! [`Context::client`]: ../context/struct.Context.html#method.client
+//! [`Shio::client`]: ../struct.Shio.html#method.client
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use futures::{future, Future};
+use futures::future::Either;
+use hyper::{self, Uri};
+use hyper::client::HttpConnector;
+use tokio_core::reactor::{Handle, Timeout};
+
+use http::header::{Header, Headers, UserAgent};
+use util::typemap::Key;
+use ext::{BoxFuture, FutureExt};
+
+/// A builder for a [`Client`].
+///
+/// A `Builder` is `Send + Sync` so that one client may be built for each worker thread.
+///
+/// [`Client`]: struct.Client.html
+#[derive(Clone, Debug)]
+pub struct Builder {
+    keep_alive: bool,
+    keep_alive_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            keep_alive_timeout: Some(Duration::from_secs(90)),
+            timeout: Some(Duration::from_secs(30)),
+            user_agent: Some(concat!("shio/", env!("CARGO_PKG_VERSION")).to_owned()),
+        }
+    }
+}
+
+impl Builder {
+    /// Constructs a new `Builder` with the default configuration.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets whether connections are kept alive and reused between requests.
+    ///
+    /// Defaults to `true`.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Sets how long an idle connection is kept alive.
+    ///
+    /// Defaults to 90 seconds. `None` keeps idle connections alive indefinitely.
+    pub fn keep_alive_timeout(mut self, keep_alive_timeout: Option<Duration>) -> Self {
+        self.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    /// Sets how long to wait for the response head before failing a request with
+    /// `hyper::Error::Timeout`.
+    ///
+    /// Defaults to 30 seconds. `None` waits indefinitely.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the `User-Agent` sent with requests that do not specify their own.
+    ///
+    /// Defaults to `shio/<version>`.
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: Option<S>) -> Self {
+        self.user_agent = user_agent.map(Into::into);
+        self
+    }
+
+    /// Constructs a new `Client` bound to the event loop referenced by `handle`.
+    pub fn build(&self, handle: &Handle) -> Client {
+        let mut headers = Headers::new();
+
+        if let Some(ref user_agent) = self.user_agent {
+            headers.set(UserAgent::new(user_agent.clone()));
+        }
+
+        Client {
+            inner: hyper::Client::configure()
+                .keep_alive(self.keep_alive)
+                .keep_alive_timeout(self.keep_alive_timeout)
+                .build(handle),
+            handle: handle.clone(),
+            timeout: self.timeout,
+            headers,
+        }
+    }
+}
+
+/// An outbound HTTP client.
+///
+/// Cloning a `Client` is cheap; clones share the same pool of connections.
+#[derive(Clone, Debug)]
+pub struct Client {
+    inner: hyper::Client<HttpConnector>,
+    handle: Handle,
+    timeout: Option<Duration>,
+    headers: Headers,
+}
+
+impl Client {
+    /// Constructs a new `Client` with the default configuration.
+    pub fn new(handle: &Handle) -> Self {
+        Builder::new().build(handle)
+    }
+
+    /// Returns a copy of this client that sends `header` with every request that does
+    /// not specify its own.
+    pub fn with_header<H: Header>(mut self, header: H) -> Self {
+        self.headers.set(header);
+        self
+    }
+
+    /// Send a `GET` request to the supplied `Uri`.
+    pub fn get(&self, uri: Uri) -> BoxFuture<hyper::Response, hyper::Error> {
+        self.request(hyper::Request::new(hyper::Method::Get, uri))
+    }
+
+    /// Send a constructed `Request`.
+    pub fn request(&self, mut request: hyper::Request) -> BoxFuture<hyper::Response, hyper::Error> {
+        for header in self.headers.iter() {
+            if request.headers().get_raw(header.name()).is_none() {
+                request
+                    .headers_mut()
+                    .set_raw(header.name().to_owned(), header.raw().clone());
+            }
+        }
+
+        let response = self.inner.request(request);
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return response.into_box(),
+        };
+
+        let timeout = match Timeout::new(timeout, &self.handle) {
+            Ok(timeout) => timeout,
+            Err(err) => return future::err(hyper::Error::Io(err)).into_box(),
+        };
+
+        response
+            .select2(timeout)
+            .then(|result| match result {
+                Ok(Either::A((response, _))) => Ok(response),
+                Ok(Either::B(_)) => Err(hyper::Error::Timeout),
+                Err(Either::A((err, _))) => Err(err),
+                Err(Either::B((err, _))) => Err(hyper::Error::Io(err)),
+            })
+            .into_box()
+    }
+}
+
+/// The client of a worker thread, created on first use.
+pub(crate) struct Lazy {
+    builder: Builder,
+    handle: Handle,
+    client: RefCell<Option<Client>>,
+}
+
+impl Lazy {
+    pub(crate) fn new(builder: Builder, handle: Handle) -> Self {
+        Self {
+            builder,
+            handle,
+            client: RefCell::new(None),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Client {
+        self.client
+            .borrow_mut()
+            .get_or_insert_with(|| self.builder.build(&self.handle))
+            .clone()
+    }
+}
+
+impl Key for Lazy {
+    type Value = Self;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{self, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{future, Future, Stream};
+    use hyper::server::Http;
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::Core;
+
+    use {Context, Handler, Response};
+    use http::header::UserAgent;
+    use middleware::RequestId;
+    use service::Service;
+    use trace::{TraceContext, Traceparent};
+    use util::typemap::TypeMap;
+    use ext::BoxFuture;
+    use super::Builder;
+
+    fn serve<H: Handler + 'static>(core: &Core, handler: H) -> SocketAddr
+    where
+        H::Result: 'static,
+        <H::Result as ::futures::IntoFuture>::Error: ::std::fmt::Debug + Send,
+    {
+        let handle = core.handle();
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = TcpListener::from_listener(listener, &addr, &handle).unwrap();
+        let service = Service::new(Arc::new(handler), handle.clone(), Arc::new(TypeMap::custom()));
+
+        let server = listener.incoming().for_each(move |(socket, _)| {
+            let connection = Http::<::hyper::Chunk>::new()
+                .serve_connection(socket, service.clone())
+                .map(|_| ())
+                .map_err(|_| ());
+
+            handle.spawn(connection);
+            Ok(())
+        });
+
+        core.handle().spawn(server.map_err(|_| ()));
+        addr
+    }
+
+    fn user_agent(ctx: Context) -> Response {
+        Response::with(
+            ctx.headers()
+                .get::<UserAgent>()
+                .map_or(String::new(), |user_agent| user_agent.to_string()),
+        )
+    }
+
+    fn get_body(core: &mut Core, client: &super::Client, addr: SocketAddr) -> String {
+        let uri = format!("http://{}/", addr).parse().unwrap();
+        let work = client.get(uri).and_then(|res| res.body().concat2());
+
+        String::from_utf8(core.run(work).unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_default_user_agent() {
+        let mut core = Core::new().unwrap();
+        let addr = serve(&core, user_agent);
+        let client = Builder::new().build(&core.handle());
+
+        assert!(get_body(&mut core, &client, addr).starts_with("shio/"));
+    }
+
+    #[test]
+    fn test_custom_user_agent() {
+        let mut core = Core::new().unwrap();
+        let addr = serve(&core, user_agent);
+        let client = Builder::new()
+            .user_agent(Some("test-agent"))
+            .build(&core.handle());
+
+        assert_eq!(get_body(&mut core, &client, addr), "test-agent");
+    }
+
+    #[test]
+    fn test_forwards_request_id() {
+        fn request_id(ctx: Context) -> Response {
+            Response::with(
+                ctx.headers()
+                    .get::<RequestId>()
+                    .map_or(String::new(), |id| id.to_string()),
+            )
+        }
+
+        let mut core = Core::new().unwrap();
+        let addr = serve(&core, request_id);
+        let ctx: Context = Context::build(&core.handle())
+            .put::<RequestId>(RequestId::parse("req-1").unwrap())
+            .into();
+
+        assert_eq!(get_body(&mut core, &ctx.client(), addr), "req-1");
+    }
+
+    #[test]
+    fn test_forwards_traceparent() {
+        fn traceparent(ctx: Context) -> Response {
+            Response::with(
+                ctx.headers()
+                    .get::<Traceparent>()
+                    .map_or(String::new(), |parent| parent.to_string()),
+            )
+        }
+
+        let mut core = Core::new().unwrap();
+        let addr = serve(&core, traceparent);
+        let trace = TraceContext::new();
+        let ctx: Context = Context::build(&core.handle())
+            .put::<TraceContext>(trace.clone())
+            .into();
+
+        assert_eq!(
+            get_body(&mut core, &ctx.client(), addr),
+            trace.traceparent().to_string()
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        fn never(_: Context) -> BoxFuture<Response, ()> {
+            Box::new(future::empty())
+        }
+
+        let mut core = Core::new().unwrap();
+        let addr = serve(&core, never);
+        let client = Builder::new()
+            .timeout(Some(Duration::from_millis(10)))
+            .build(&core.handle());
+
+        let uri = format!("http://{}/", addr).parse().unwrap();
+
+        match core.run(client.get(uri)) {
+            Err(::hyper::Error::Timeout) => {}
+            _ => panic!("expected request to time out"),
+        }
+    }
+}
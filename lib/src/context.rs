@@ -1,10 +1,15 @@
 use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use tokio_core::reactor::Handle;
 use unsafe_any::UnsafeAny;
 
 use util::typemap::TypeMap;
+use client::{self, Client};
+use middleware::RequestId;
 use request::Request;
+use trace::TraceContext;
 use state::{FromState, State};
 use Data;
 pub use state::Key;
@@ -37,12 +42,66 @@ impl Context {
         }
     }
 
+    /// Creates a new builder-style object to manufacture a `Context` associated with the event
+    /// loop referenced by `handle`.
+    ///
+    /// ```rust
+    /// # extern crate shio;
+    /// # extern crate tokio_core;
+    /// # use shio::prelude::*;
+    /// # use tokio_core::reactor::Core;
+    /// # fn main() {
+    /// let core = Core::new().unwrap();
+    /// let ctx: Context = Context::build(&core.handle())
+    ///     .request(Request::build().method(Method::POST).uri("/jobs".parse().unwrap()).into())
+    ///     .data("{\"id\": 20}")
+    ///     .into();
+    /// # }
+    /// ```
+    pub fn build(handle: &Handle) -> Builder {
+        Builder::new(handle)
+    }
+
     /// Return a reference to a handle to the event loop this `Context` is associated with.
     #[inline]
     pub fn handle(&self) -> &Handle {
         &self.handle
     }
 
+    /// Returns the outbound HTTP client of the worker thread handling this request.
+    ///
+    /// The client is created on first use and shared by every request handled on the same
+    /// worker thread, so connections to other services are kept alive and reused. If this
+    /// request has a [`RequestId`] or a [`TraceContext`], the client sends it with every
+    /// request.
+    ///
+    /// [`RequestId`]: ../middleware/struct.RequestId.html
+    /// [`TraceContext`]: ../trace/struct.TraceContext.html
+    pub fn client(&self) -> Client {
+        let client = match self.local().try_get::<client::Lazy>() {
+            Some(client) => client.get(),
+            None => Client::new(&self.handle),
+        };
+
+        let client = match self.try_get::<RequestId>() {
+            Some(id) => client.with_header(id.clone()),
+            None => client,
+        };
+
+        match self.try_get::<TraceContext>() {
+            Some(trace) => {
+                let client = client.with_header(trace.traceparent());
+
+                match trace.state {
+                    Some(ref state) => client.with_header(state.clone()),
+                    None => client,
+                }
+            }
+
+            None => client,
+        }
+    }
+
     /// Take the request body.
     pub fn data(self) -> Data {
         self.body
@@ -75,6 +134,16 @@ impl Context {
         self.state.shared()
     }
 
+    /// Gets a reference to the state local to the worker thread handling this request.
+    ///
+    /// Values in this map are created by the factories registered with `Shio::manage_local`
+    /// and may be bound to the event loop referenced by [`Context::handle`].
+    ///
+    /// [`Context::handle`]: #method.handle
+    pub fn local(&self) -> &TypeMap {
+        self.state.local()
+    }
+
     /// Deconstruct current context
     pub fn deconstruct(self) -> (Handle, State, Request, Data) {
         (self.handle, self.state, self.request, self.body)
@@ -88,3 +157,86 @@ impl Deref for Context {
         &self.request
     }
 }
+
+/// A `Context` builder.
+///
+/// This type can be used to construct a [`Context`] through a builder-like pattern, for
+/// example to replay a queued request through a [`Router`].
+///
+/// [`Context`]: struct.Context.html
+/// [`Router`]: ../router/struct.Router.html
+pub struct Builder {
+    handle: Handle,
+    request: Request,
+    body: Data,
+    request_state: TypeMap,
+    shared_state: Arc<TypeMap<UnsafeAny + Send + Sync>>,
+    local_state: Rc<TypeMap>,
+}
+
+impl Builder {
+    /// Creates a new `Builder` to construct a [`Context`] associated with the event loop
+    /// referenced by `handle`.
+    ///
+    /// By default, the context has the default [`Request`], an empty body, and empty state.
+    ///
+    /// [`Context`]: struct.Context.html
+    /// [`Request`]: ../request/struct.Request.html
+    pub fn new(handle: &Handle) -> Self {
+        Self {
+            handle: handle.clone(),
+            request: Request::build().into(),
+            body: Data::default(),
+            request_state: TypeMap::new(),
+            shared_state: Arc::new(TypeMap::custom()),
+            local_state: Rc::new(TypeMap::new()),
+        }
+    }
+
+    /// Set the request.
+    #[inline]
+    pub fn request(mut self, request: Request) -> Self {
+        self.request = request;
+        self
+    }
+
+    /// Set the request body.
+    #[inline]
+    pub fn data<D: Into<Data>>(mut self, data: D) -> Self {
+        self.body = data.into();
+        self
+    }
+
+    /// Puts a value into the request state.
+    #[inline]
+    pub fn put<K: Key>(mut self, value: K::Value) -> Self {
+        self.request_state.put::<K>(value);
+        self
+    }
+
+    /// Set the state shared across all requests.
+    #[inline]
+    pub fn shared_state(mut self, shared_state: Arc<TypeMap<UnsafeAny + Send + Sync>>) -> Self {
+        self.shared_state = shared_state;
+        self
+    }
+
+    /// Set the worker-local state.
+    #[inline]
+    pub fn local_state(mut self, local_state: TypeMap) -> Self {
+        self.local_state = Rc::new(local_state);
+        self
+    }
+}
+
+impl From<Builder> for Context {
+    fn from(builder: Builder) -> Self {
+        let state = State::from_parts(
+            builder.request_state,
+            builder.shared_state,
+            builder.local_state,
+        );
+
+        Self::new(builder.handle, builder.request, state, builder.body)
+    }
+}
@@ -3,12 +3,47 @@ use futures::{Poll, Stream};
 
 use errors::Error;
 
+/// The body of a request, as a stream of chunks.
 #[derive(Default)]
-pub struct Data(hyper::Body);
+pub struct Data(Inner);
+
+enum Inner {
+    Body(hyper::Body),
+    Stream(Box<Stream<Item = hyper::Chunk, Error = Error>>),
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Inner::Body(hyper::Body::default())
+    }
+}
 
 impl Data {
     pub(crate) fn new(body: hyper::Body) -> Self {
-        Data(body)
+        Data(Inner::Body(body))
+    }
+
+    /// Constructs `Data` from a stream of chunks.
+    ///
+    /// ```rust
+    /// # extern crate futures;
+    /// # extern crate shio;
+    /// # use futures::stream;
+    /// # use shio::Data;
+    /// # fn main() {
+    /// let chunks: Vec<Result<_, shio::Error>> = vec![Ok("Hello "), Ok("World")];
+    /// let data = Data::from_stream(stream::iter_result(chunks));
+    /// # }
+    /// ```
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream + 'static,
+        S::Item: Into<hyper::Chunk>,
+        S::Error: Into<Error>,
+    {
+        Data(Inner::Stream(Box::new(
+            stream.map(Into::into).map_err(Into::into),
+        )))
     }
 }
 
@@ -18,6 +53,61 @@ impl Stream for Data {
 
     #[inline]
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        self.0.poll().map_err(Error::from)
+        match self.0 {
+            Inner::Body(ref mut body) => body.poll().map_err(Error::from),
+            Inner::Stream(ref mut stream) => stream.poll(),
+        }
+    }
+}
+
+impl From<hyper::Body> for Data {
+    fn from(body: hyper::Body) -> Self {
+        Data::new(body)
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(bytes: Vec<u8>) -> Self {
+        Data::new(bytes.into())
+    }
+}
+
+impl From<&'static [u8]> for Data {
+    fn from(bytes: &'static [u8]) -> Self {
+        Data::new(bytes.into())
+    }
+}
+
+impl From<String> for Data {
+    fn from(text: String) -> Self {
+        Data::new(text.into())
+    }
+}
+
+impl From<&'static str> for Data {
+    fn from(text: &'static str) -> Self {
+        Data::new(text.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future, Stream};
+
+    use super::Data;
+
+    #[test]
+    fn test_from_bytes() {
+        let data = Data::from("Hello World");
+
+        assert_eq!(&*data.concat2().wait().unwrap(), b"Hello World");
+    }
+
+    #[test]
+    fn test_from_stream() {
+        let chunks: Vec<Result<_, ::Error>> = vec![Ok("Hello "), Ok("World")];
+        let data = Data::from_stream(stream::iter_result(chunks));
+
+        assert_eq!(&*data.concat2().wait().unwrap(), b"Hello World");
     }
 }
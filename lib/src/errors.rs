@@ -5,6 +5,8 @@ use std::error::Error as StdError;
 use std::io;
 
 use hyper;
+#[cfg(feature = "serde")]
+use serde_json;
 
 /// An error that occurs during `Shio::listen` or `Shio::run`.
 #[derive(Debug)]
@@ -59,6 +61,8 @@ pub struct Error { inner: ErrorKind }
 enum ErrorKind {
     Listen(ListenError),
     Hyper(hyper::Error),
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
 }
 
 impl From<ListenError> for Error {
@@ -73,11 +77,20 @@ impl From<hyper::Error> for Error {
     }
 }
 
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self { inner: ErrorKind::Json(err) }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.inner {
             ErrorKind::Hyper(ref err) => err.fmt(f),
             ErrorKind::Listen(ref err) => err.fmt(f),
+            #[cfg(feature = "serde")]
+            ErrorKind::Json(ref err) => err.fmt(f),
         }
     }
 }
@@ -87,6 +100,8 @@ impl StdError for Error {
         match self.inner {
             ErrorKind::Hyper(ref err) => err.description(),
             ErrorKind::Listen(ref err) => err.description(),
+            #[cfg(feature = "serde")]
+            ErrorKind::Json(ref err) => err.description(),
         }
     }
 
@@ -94,6 +109,8 @@ impl StdError for Error {
         match self.inner {
             ErrorKind::Hyper(ref err) => err.cause(),
             ErrorKind::Listen(ref err) => err.cause(),
+            #[cfg(feature = "serde")]
+            ErrorKind::Json(ref err) => err.cause(),
         }
     }
 }
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use hyper::Method;
+
+use context::Context;
+use handler::Handler;
+use http::StatusCode;
+use http::header::{ContentType, Headers};
+use response::{Body, Response};
+use util::percent;
+use super::{method_not_allowed, mime, request_path, respond, Entity};
+
+/// A file compiled into the binary.
+///
+/// A table of assets is usually generated by a build script, with `include_bytes!` for the
+/// contents and a hash of the contents to use as the `ETag`.
+#[derive(Clone, Copy, Debug)]
+pub struct Asset {
+    /// The path of the asset, relative to where the [`Assets`] handler is mounted.
+    ///
+    /// [`Assets`]: struct.Assets.html
+    pub path: &'static str,
+
+    /// The contents of the asset.
+    pub contents: &'static [u8],
+
+    /// A hash of the contents, which changes whenever the contents change.
+    pub hash: &'static str,
+}
+
+/// A [`Handler`] that serves [`Asset`]s compiled into the binary.
+///
+/// Like [`StaticFiles`], the asset is taken from the `path` parameter of the route, or from
+/// the whole request path if the route has no such parameter.
+///
+/// To support client-side routing in a single-page app, a request for an unknown path
+/// without a file extension is answered with the fallback asset, `index.html` by default.
+/// Paths under a prefix given to `Assets::exclude` never fall back.
+///
+/// ```rust,no_run
+/// # use shio::prelude::*;
+/// # use shio::files::{Asset, Assets};
+/// static ASSETS: &[Asset] = &[
+///     Asset { path: "index.html", contents: b"<div id=app></div>", hash: "8b1a9953" },
+///     Asset { path: "app.js", contents: b"console.log('Hello')", hash: "cd5f6b9a" },
+/// ];
+///
+/// Shio::default()
+///     .route((Method::GET, "/{path: .*}", Assets::new(ASSETS).exclude("/api/")))
+///     .run(":7878")
+///     .unwrap();
+/// ```
+///
+/// [`Handler`]: ../trait.Handler.html
+/// [`Asset`]: struct.Asset.html
+/// [`StaticFiles`]: struct.StaticFiles.html
+#[derive(Clone, Debug)]
+pub struct Assets {
+    assets: HashMap<&'static str, &'static Asset>,
+    fallback: Option<String>,
+    excluded: Vec<String>,
+}
+
+impl Assets {
+    /// Constructs a new `Assets` that serves the assets in `table`.
+    pub fn new(table: &'static [Asset]) -> Self {
+        Self {
+            assets: table
+                .iter()
+                .map(|asset| (asset.path.trim_left_matches('/'), asset))
+                .collect(),
+            fallback: Some("index.html".into()),
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Sets the asset served for unknown paths.
+    ///
+    /// Defaults to `index.html`. `None` answers unknown paths with `404 Not Found`.
+    pub fn fallback<S: Into<String>>(mut self, fallback: Option<S>) -> Self {
+        self.fallback = fallback.map(Into::into);
+        self
+    }
+
+    /// Never fall back for request paths that start with `prefix`, such as `/api/`.
+    pub fn exclude<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.excluded.push(prefix.into());
+        self
+    }
+
+    fn find(&self, ctx: &Context, path: &str) -> Option<&'static Asset> {
+        let path = path.trim_left_matches('/');
+        let asset = if path.is_empty() || path.ends_with('/') {
+            self.assets.get(&*format!("{}index.html", path))
+        } else {
+            self.assets.get(path)
+        };
+
+        if let Some(asset) = asset {
+            return Some(asset);
+        }
+
+        // Only paths that look like routes of the app fall back; a missing script or
+        // stylesheet should still be a 404
+        let is_file = path.rsplit('/').next().map_or(false, |name| name.contains('.'));
+        let is_excluded = self.excluded
+            .iter()
+            .any(|prefix| ctx.path().starts_with(&**prefix));
+
+        if is_file || is_excluded {
+            return None;
+        }
+
+        self.fallback
+            .as_ref()
+            .and_then(|fallback| self.assets.get(fallback.trim_left_matches('/')))
+            .cloned()
+    }
+}
+
+impl Handler for Assets {
+    type Result = Response;
+
+    fn call(&self, ctx: Context) -> Self::Result {
+        match *ctx.method() {
+            Method::Get | Method::Head => {}
+            _ => return method_not_allowed(),
+        }
+
+        let asset = percent::decode(request_path(&ctx)).and_then(|path| self.find(&ctx, &path));
+        let asset = match asset {
+            Some(asset) => asset,
+            None => return Response::with(StatusCode::NotFound),
+        };
+
+        let mut headers = Headers::new();
+        headers.set(ContentType(mime::guess(Path::new(asset.path))));
+
+        let entity = Entity::hashed(asset.contents.len() as u64, asset.hash);
+
+        respond(&ctx, &entity, headers, |offset, len| {
+            let (offset, len) = (offset as usize, len as usize);
+
+            Body::from(&asset.contents[offset..offset + len])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use http::header::{ContentType, EntityTag, IfNoneMatch};
+    use test::TestClient;
+    use super::{Asset, Assets};
+
+    static ASSETS: &[Asset] = &[
+        Asset {
+            path: "index.html",
+            contents: b"<div id=app></div>",
+            hash: "1",
+        },
+        Asset {
+            path: "/js/app.js",
+            contents: b"console.log('Hello')",
+            hash: "2",
+        },
+    ];
+
+    #[test]
+    fn test_serve_asset() {
+        let client = TestClient::new(Assets::new(ASSETS));
+
+        client
+            .get("/js/app.js")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header(&ContentType("application/javascript; charset=utf-8".parse().unwrap()))
+            .assert_body("console.log('Hello')");
+
+        client.get("/").send().assert_body("<div id=app></div>");
+
+        client
+            .get("/js/app.js")
+            .header(IfNoneMatch::Items(vec![EntityTag::strong("2".into())]))
+            .send()
+            .assert_status(StatusCode::NotModified);
+    }
+
+    #[test]
+    fn test_fallback() {
+        let client = TestClient::new(Assets::new(ASSETS).exclude("/api/"));
+
+        client
+            .get("/settings/profile")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header(&ContentType("text/html; charset=utf-8".parse().unwrap()))
+            .assert_body("<div id=app></div>");
+
+        client.get("/js/missing.js").send().assert_status(StatusCode::NotFound);
+        client.get("/api/users").send().assert_status(StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_no_fallback() {
+        let client = TestClient::new(Assets::new(ASSETS).fallback(None::<String>));
+
+        client.get("/settings").send().assert_status(StatusCode::NotFound);
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use hyper::mime::{self, Mime};
+
+/// Guess the media type of a file from its extension.
+///
+/// Text types are assumed to be UTF-8. Unknown extensions are served as
+/// `application/octet-stream`.
+pub(super) fn guess(path: &Path) -> Mime {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    let media_type = match extension.as_ref().map(|extension| &**extension) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "application/javascript; charset=utf-8",
+        Some("json") | Some("map") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("xml") => "text/xml; charset=utf-8",
+        Some("webmanifest") => "application/manifest+json",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => return mime::APPLICATION_OCTET_STREAM,
+    };
+
+    media_type.parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::guess;
+
+    #[test]
+    fn test_guess() {
+        assert_eq!(guess(Path::new("index.HTML")).to_string(), "text/html; charset=utf-8");
+        assert_eq!(guess(Path::new("app.js")).to_string(), "application/javascript; charset=utf-8");
+        assert_eq!(guess(Path::new("logo.svg")).to_string(), "image/svg+xml");
+        assert_eq!(guess(Path::new("LICENSE")).to_string(), "application/octet-stream");
+    }
+}
@@ -0,0 +1,179 @@
+//! Handlers that serve files.
+//!
+//! [`StaticFiles`] serves files from a directory on disk, and [`Assets`] serves files compiled
+//! into the binary. Responses carry an `ETag` validator, so conditional requests are answered
+//! with `304 Not Modified`, and a single byte range may be requested with `Range`.
+//!
+//! [`StaticFiles`]: struct.StaticFiles.html
+//! [`Assets`]: struct.Assets.html
+
+mod assets;
+mod mime;
+mod static_files;
+
+pub use self::assets::{Asset, Assets};
+pub use self::static_files::StaticFiles;
+
+use std::time::SystemTime;
+
+use hyper::Method;
+
+use context::Context;
+use request::Request;
+use response::{Body, Response};
+use router::Parameters;
+use http::StatusCode;
+use http::header::{AcceptRanges, Allow, ByteRangeSpec, ContentLength, ContentRange,
+                   ContentRangeSpec, ETag, EntityTag, Headers, HttpDate, IfModifiedSince,
+                   IfNoneMatch, IfRange, LastModified, Range, RangeUnit};
+
+/// The validators and length of a file about to be served.
+struct Entity {
+    len: u64,
+    modified: Option<HttpDate>,
+    etag: EntityTag,
+}
+
+impl Entity {
+    /// An entity identified by its length and modification time, such as a file on disk.
+    fn new(len: u64, modified: Option<SystemTime>) -> Self {
+        let modified = modified.map(HttpDate::from);
+        let secs = modified.map_or(0, |modified| {
+            SystemTime::from(modified)
+                .duration_since(::std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0)
+        });
+
+        Self {
+            len,
+            modified,
+            etag: EntityTag::strong(format!("{:x}-{:x}", len, secs)),
+        }
+    }
+
+    /// An entity identified by a hash of its contents.
+    fn hashed(len: u64, hash: &str) -> Self {
+        Self {
+            len,
+            modified: None,
+            etag: EntityTag::strong(hash.into()),
+        }
+    }
+
+    /// Whether the client already has this entity, as described by the conditional headers
+    /// of `request`.
+    fn is_fresh(&self, request: &Request) -> bool {
+        // If-None-Match takes precedence over If-Modified-Since when both are sent
+        match request.headers().get::<IfNoneMatch>() {
+            Some(&IfNoneMatch::Any) => return true,
+            Some(&IfNoneMatch::Items(ref tags)) => {
+                return tags.iter().any(|tag| tag.weak_eq(&self.etag))
+            }
+
+            None => {}
+        }
+
+        match (request.headers().get::<IfModifiedSince>(), self.modified) {
+            (Some(&IfModifiedSince(since)), Some(modified)) => modified <= since,
+            _ => false,
+        }
+    }
+
+    /// The range of bytes requested by `request`, if it should be honored.
+    fn range<'a>(&self, request: &'a Request) -> Option<&'a ByteRangeSpec> {
+        let spec = match request.headers().get::<Range>() {
+            Some(&Range::Bytes(ref specs)) if specs.len() == 1 => &specs[0],
+
+            // Multiple ranges are allowed to be answered with the whole entity
+            _ => return None,
+        };
+
+        match request.headers().get::<IfRange>() {
+            None => Some(spec),
+            Some(&IfRange::EntityTag(ref tag)) if tag.strong_eq(&self.etag) => Some(spec),
+            Some(&IfRange::Date(date)) if Some(date) == self.modified => Some(spec),
+
+            // The entity has changed since the client requested the rest of it
+            Some(_) => None,
+        }
+    }
+}
+
+/// Responds to a `GET` or `HEAD` request for `entity`, honoring conditional and range
+/// requests.
+///
+/// `headers` are sent with every response except `304 Not Modified`, and `body` is called
+/// with the offset and length of the bytes to send.
+fn respond<F>(request: &Request, entity: &Entity, headers: Headers, body: F) -> Response
+where
+    F: FnOnce(u64, u64) -> Body,
+{
+    let mut response = Response::new();
+
+    {
+        let response_headers = response.headers_mut();
+        response_headers.set(ETag(entity.etag.clone()));
+
+        if let Some(modified) = entity.modified {
+            response_headers.set(LastModified(modified));
+        }
+    }
+
+    if entity.is_fresh(request) {
+        response.set_status(StatusCode::NotModified);
+        return response;
+    }
+
+    response.headers_mut().extend(headers.iter());
+    response.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+
+    let (offset, len) = match entity.range(request) {
+        Some(spec) => match spec.to_satisfiable_range(entity.len) {
+            Some((first, last)) => {
+                response.set_status(StatusCode::PartialContent);
+                response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((first, last)),
+                    instance_length: Some(entity.len),
+                }));
+
+                (first, last - first + 1)
+            }
+
+            None => {
+                response.set_status(StatusCode::RangeNotSatisfiable);
+                response.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(entity.len),
+                }));
+
+                return response;
+            }
+        },
+
+        None => (0, entity.len),
+    };
+
+    response.headers_mut().set(ContentLength(len));
+
+    if *request.method() != Method::Head {
+        response.set_body(body(offset, len));
+    }
+
+    response
+}
+
+/// Responds to a request with a method other than `GET` or `HEAD`.
+fn method_not_allowed() -> Response {
+    Response::build()
+        .status(StatusCode::MethodNotAllowed)
+        .header(Allow(vec![Method::Get, Method::Head]))
+        .into()
+}
+
+/// The path captured by the `path` parameter of the route, or the whole request path.
+fn request_path(ctx: &Context) -> &str {
+    ctx.try_get::<Parameters>()
+        .and_then(|parameters| parameters.name("path"))
+        .unwrap_or_else(|| ctx.path())
+}
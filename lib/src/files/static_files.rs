@@ -0,0 +1,504 @@
+use std::cmp;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use futures::{future, Async, Future, Poll, Stream};
+use futures_cpupool::{self, CpuFuture, CpuPool};
+use hyper::{self, Chunk, Method};
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use handler::Handler;
+use http::StatusCode;
+use http::header::{q, AcceptEncoding, ContentEncoding, ContentType, Encoding, Headers, Location,
+                   Vary};
+use response::{Body, Response};
+use util::percent;
+use super::{method_not_allowed, mime, request_path, respond, Entity};
+
+// Files are read from disk in chunks of at most this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    // Shared by every `StaticFiles` that isn't given its own pool
+    static ref POOL: CpuPool = futures_cpupool::Builder::new()
+        .pool_size(4)
+        .name_prefix("shio-files-")
+        .create();
+}
+
+/// A [`Handler`] that serves files from a directory.
+///
+/// The file is taken from the `path` parameter of the route, or from the whole request path
+/// if the route has no such parameter. Paths that would escape the root directory are
+/// answered with `404 Not Found`, and a directory requested without a trailing slash is
+/// redirected to the path with one so that relative links in its index resolve.
+///
+/// Files are opened and read on a thread pool, so that a slow disk doesn't hold up the other
+/// requests on the worker's event loop.
+///
+/// ```rust,no_run
+/// # use shio::prelude::*;
+/// # use shio::files::StaticFiles;
+/// Shio::default()
+///     .route((Method::GET, "/assets/{path: .*}", StaticFiles::new("public")))
+///     .run(":7878")
+///     .unwrap();
+/// ```
+///
+/// [`Handler`]: ../trait.Handler.html
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+    precompressed: bool,
+    pool: CpuPool,
+}
+
+impl StaticFiles {
+    /// Constructs a new `StaticFiles` that serves files under `root`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            index: Some("index.html".into()),
+            precompressed: false,
+            pool: POOL.clone(),
+        }
+    }
+
+    /// Sets the file served when a directory is requested.
+    ///
+    /// Defaults to `index.html`. `None` answers requests for directories with
+    /// `404 Not Found`.
+    pub fn index<S: Into<String>>(mut self, index: Option<S>) -> Self {
+        self.index = index.map(Into::into);
+        self
+    }
+
+    /// Sets whether a `.br` or `.gz` file next to the requested file is served in its place
+    /// when the client accepts that encoding.
+    ///
+    /// Defaults to `false`.
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Sets the thread pool files are read on.
+    ///
+    /// Defaults to a pool of 4 threads shared by every `StaticFiles`, started on first use.
+    pub fn pool(mut self, pool: CpuPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Map a decoded request path to a path under the root directory.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+
+        for segment in path.split('/') {
+            if segment.is_empty() || segment == "." {
+                continue;
+            }
+
+            // Operating systems reject paths with a NUL byte rather than report them missing
+            if segment.contains('\0') {
+                return None;
+            }
+
+            // Anything that is not a plain file name, such as `..`, a drive prefix, or a
+            // separator on platforms that use `\`, could escape the root
+            let mut components = Path::new(segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(name)), None) if name == segment => resolved.push(name),
+                _ => return None,
+            }
+        }
+
+        Some(resolved)
+    }
+
+    /// The encodings of precompressed files the client accepts, with their extensions.
+    fn accepted_encodings(&self, ctx: &Context) -> Vec<(Encoding, &'static str)> {
+        let accepted = match ctx.headers().get::<AcceptEncoding>() {
+            Some(accepted) => accepted,
+            None => return Vec::new(),
+        };
+
+        [(Encoding::Brotli, "br"), (Encoding::Gzip, "gz")]
+            .iter()
+            .filter(|&&(ref encoding, _)| {
+                accepted
+                    .iter()
+                    .any(|item| item.item == *encoding && item.quality > q(0))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Handler for StaticFiles {
+    type Result = BoxFuture<Response, hyper::Error>;
+
+    fn call(&self, ctx: Context) -> Self::Result {
+        match *ctx.method() {
+            Method::Get | Method::Head => {}
+            _ => return future::ok(method_not_allowed()).into_box(),
+        }
+
+        let path = percent::decode(request_path(&ctx)).and_then(|path| self.resolve(&path));
+        let path = match path {
+            Some(path) => path,
+            None => return future::ok(Response::with(StatusCode::NotFound)).into_box(),
+        };
+
+        let index = self.index.clone();
+        let is_dir_path = ctx.uri().path().ends_with('/');
+        let precompressed = self.precompressed;
+        let encodings = if precompressed {
+            self.accepted_encodings(&ctx)
+        } else {
+            Vec::new()
+        };
+
+        let pool = self.pool.clone();
+
+        self.pool
+            .spawn_fn(move || open(path, index, is_dir_path, encodings))
+            .then(move |opened| {
+                let opened = match opened {
+                    Ok(opened) => opened,
+                    Err(StatusCode::MovedPermanently) => return Ok(redirect_to_dir(&ctx)),
+                    Err(status) => return Ok(Response::with(status)),
+                };
+
+                let mut headers = Headers::new();
+                headers.set(ContentType(mime::guess(&opened.path)));
+
+                if precompressed {
+                    headers.set(Vary::Items(vec!["Accept-Encoding".parse().unwrap()]));
+                }
+
+                if let Some(encoding) = opened.encoding {
+                    headers.set(ContentEncoding(vec![encoding]));
+                }
+
+                let metadata = opened.metadata;
+                let entity = Entity::new(metadata.len(), metadata.modified().ok());
+                let file = opened.file;
+
+                Ok(respond(&ctx, &entity, headers, |offset, len| {
+                    Body::wrap_stream(FileStream {
+                        pool,
+                        file: Some(file),
+                        offset: Some(offset),
+                        remaining: len,
+                        read: None,
+                    })
+                }))
+            })
+            .into_box()
+    }
+}
+
+/// A file opened to be served.
+struct Opened {
+    // The requested path, which decides the content type
+    path: PathBuf,
+    file: File,
+    metadata: Metadata,
+    // Set when a precompressed file is served instead
+    encoding: Option<Encoding>,
+}
+
+/// Opens the file at `path`, or its `index` if it is a directory, preferring a precompressed
+/// file in one of `encodings`. Runs on the thread pool.
+///
+/// Fails with `301 Moved Permanently` for a directory with an index that was requested
+/// without a trailing slash.
+fn open(
+    mut path: PathBuf,
+    index: Option<String>,
+    is_dir_path: bool,
+    encodings: Vec<(Encoding, &'static str)>,
+) -> Result<Opened, StatusCode> {
+    let mut metadata = fs::metadata(&path).map_err(|err| error_status(&err))?;
+
+    if metadata.is_dir() {
+        match index {
+            Some(_) if !is_dir_path => return Err(StatusCode::MovedPermanently),
+            Some(index) => path.push(index),
+            None => return Err(StatusCode::NotFound),
+        }
+
+        metadata = fs::metadata(&path).map_err(|err| error_status(&err))?;
+    }
+
+    if !metadata.is_file() {
+        return Err(StatusCode::NotFound);
+    }
+
+    let mut served = path.clone();
+    let mut served_encoding = None;
+
+    for (encoding, extension) in encodings {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(extension);
+
+        let compressed = PathBuf::from(name);
+        match fs::metadata(&compressed) {
+            Ok(ref compressed_metadata) if compressed_metadata.is_file() => {
+                metadata = compressed_metadata.clone();
+                served = compressed;
+                served_encoding = Some(encoding);
+                break;
+            }
+
+            _ => {}
+        }
+    }
+
+    let file = File::open(&served).map_err(|err| error_status(&err))?;
+
+    Ok(Opened {
+        path,
+        file,
+        metadata,
+        encoding: served_encoding,
+    })
+}
+
+/// Redirects a request for a directory to the same path with a trailing slash.
+fn redirect_to_dir(ctx: &Context) -> Response {
+    // A path starting with `//` would be taken as the host of another site
+    let mut location = format!("/{}/", ctx.uri().path().trim_start_matches('/'));
+
+    if let Some(query) = ctx.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    Response::build()
+        .status(StatusCode::MovedPermanently)
+        .header(Location::new(location))
+        .into()
+}
+
+fn error_status(err: &io::Error) -> StatusCode {
+    match err.kind() {
+        io::ErrorKind::NotFound => StatusCode::NotFound,
+        io::ErrorKind::PermissionDenied => StatusCode::Forbidden,
+        _ => {
+            error!("failed to open file: {}", err);
+            StatusCode::InternalServerError
+        }
+    }
+}
+
+/// Reads `remaining` bytes of a file on the thread pool, starting at `offset`.
+struct FileStream {
+    pool: CpuPool,
+    // Taken while a read is in progress
+    file: Option<File>,
+    offset: Option<u64>,
+    remaining: u64,
+    read: Option<CpuFuture<(File, Vec<u8>), io::Error>>,
+}
+
+impl Stream for FileStream {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, io::Error> {
+        if let Some(mut read) = self.read.take() {
+            let (file, buf) = match read.poll()? {
+                Async::Ready(read) => read,
+                Async::NotReady => {
+                    self.read = Some(read);
+                    return Ok(Async::NotReady);
+                }
+            };
+
+            if buf.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file was truncated while being served",
+                ));
+            }
+
+            self.file = Some(file);
+            self.remaining -= buf.len() as u64;
+
+            return Ok(Async::Ready(Some(buf.into())));
+        }
+
+        if self.remaining == 0 {
+            return Ok(Async::Ready(None));
+        }
+
+        let mut file = self.file.take().expect("file read after an error");
+        let offset = self.offset.take();
+        let len = cmp::min(self.remaining, CHUNK_SIZE as u64) as usize;
+
+        self.read = Some(self.pool.spawn_fn(move || {
+            if let Some(offset) = offset {
+                file.seek(SeekFrom::Start(offset))?;
+            }
+
+            let mut buf = vec![0; len];
+            let n = file.read(&mut buf)?;
+            buf.truncate(n);
+
+            Ok((file, buf))
+        }));
+
+        self.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use http::StatusCode;
+    use http::header::{AcceptEncoding, ByteRangeSpec, ContentEncoding, ContentType, ETag,
+                       Encoding, IfNoneMatch, Location, Range, qitem};
+    use test::TestClient;
+    use super::StaticFiles;
+
+    /// A directory of files to serve, removed when dropped.
+    struct Root(PathBuf);
+
+    impl Deref for Root {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn root(name: &str) -> Root {
+        let root = env::temp_dir().join(format!("shio-static-{}-{}", name, process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("hello.txt"), "Hello World").unwrap();
+        fs::write(root.join("docs").join("index.html"), "<h1>Docs</h1>").unwrap();
+
+        Root(root)
+    }
+
+    #[test]
+    fn test_serve_file() {
+        let root = root("serve");
+        let client = TestClient::new(StaticFiles::new(&*root));
+
+        client
+            .get("/hello.txt")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header(&ContentType::plaintext())
+            .assert_body("Hello World");
+
+        client
+            .get("/docs/")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_body("<h1>Docs</h1>");
+    }
+
+    #[test]
+    fn test_directory_redirect() {
+        let root = root("redirect");
+        let client = TestClient::new(StaticFiles::new(&*root));
+
+        client
+            .get("/docs?page=2")
+            .send()
+            .assert_status(StatusCode::MovedPermanently)
+            .assert_header(&Location::new("/docs/?page=2"));
+
+        client
+            .get("//docs")
+            .send()
+            .assert_header(&Location::new("/docs/"));
+
+        let client = TestClient::new(StaticFiles::new(&*root).index(None::<String>));
+        client.get("/docs").send().assert_status(StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let root = root("traversal");
+        let client = TestClient::new(StaticFiles::new(root.join("docs")));
+
+        client.get("/../hello.txt").send().assert_status(StatusCode::NotFound);
+        client.get("/%2e%2e/hello.txt").send().assert_status(StatusCode::NotFound);
+        client.get("/..%2fhello.txt").send().assert_status(StatusCode::NotFound);
+        client.get("/%00").send().assert_status(StatusCode::NotFound);
+        client.get("/index%00.html").send().assert_status(StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let root = root("not-modified");
+        let client = TestClient::new(StaticFiles::new(&*root));
+
+        let response = client.get("/hello.txt").send();
+        let etag = response.header::<ETag>().unwrap().0.clone();
+
+        client
+            .get("/hello.txt")
+            .header(IfNoneMatch::Items(vec![etag]))
+            .send()
+            .assert_status(StatusCode::NotModified)
+            .assert_body("");
+    }
+
+    #[test]
+    fn test_range() {
+        let root = root("range");
+        let client = TestClient::new(StaticFiles::new(&*root));
+
+        client
+            .get("/hello.txt")
+            .header(Range::Bytes(vec![ByteRangeSpec::FromTo(1, 3)]))
+            .send()
+            .assert_status(StatusCode::PartialContent)
+            .assert_body("ell");
+
+        client
+            .get("/hello.txt")
+            .header(Range::Bytes(vec![ByteRangeSpec::AllFrom(100)]))
+            .send()
+            .assert_status(StatusCode::RangeNotSatisfiable);
+    }
+
+    #[test]
+    fn test_precompressed() {
+        let root = root("precompressed");
+        fs::write(root.join("hello.txt.gz"), "gzipped").unwrap();
+
+        let client = TestClient::new(StaticFiles::new(&*root).precompressed(true));
+
+        client
+            .get("/hello.txt")
+            .header(AcceptEncoding(vec![qitem(Encoding::Gzip)]))
+            .send()
+            .assert_header(&ContentEncoding(vec![Encoding::Gzip]))
+            .assert_header(&ContentType::plaintext())
+            .assert_body("gzipped");
+
+        client.get("/hello.txt").send().assert_body("Hello World");
+    }
+}
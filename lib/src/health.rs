@@ -0,0 +1,414 @@
+//! Liveness and readiness probes backed by registered health checks.
+//!
+//! A check is a named function that returns a future, such as a ping of a database. Checks
+//! are registered on the [`Health`] of a `Shio`, which is kept in the shared state, and run
+//! by the [`liveness`] and [`readiness`] handlers wherever they are mounted:
+//!
+//! ```rust,no_run
+//! # use shio::prelude::*;
+//! # use shio::health;
+//! Shio::default()
+//!     .health_check("disk", |_: &Context| {
+//!         if ::std::path::Path::new("/var/lib/app").exists() {
+//!             Ok(())
+//!         } else {
+//!             Err("data directory is missing")
+//!         }
+//!     })
+//!     .route((Method::GET, "/healthz", health::liveness))
+//!     .route((Method::GET, "/readyz", health::readiness))
+//!     .run(":7878")
+//!     .unwrap();
+//! ```
+//!
+//! Every check is run concurrently, and one that takes longer than the timeout (5 seconds
+//! by default) fails. A probe responds with `200 OK` if every check passed, and with
+//! `503 Service Unavailable` otherwise, along with a JSON report:
+//!
+//! ```json
+//! {"status":"fail","checks":{"disk":{"status":"fail","duration_ms":0,"error":"..."}}}
+//! ```
+//!
+//! Liveness runs only the checks registered with `Health::liveness_check`; readiness runs
+//! all of them. Once the server begins a graceful shutdown, readiness fails with a status of
+//! `draining` so that load balancers stop sending new requests.
+//!
+//! [`Health`]: struct.Health.html
+//! [`liveness`]: fn.liveness.html
+//! [`readiness`]: fn.readiness.html
+
+use std::fmt::{self, Display, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{future, Future, IntoFuture};
+use futures::future::Either;
+use hyper;
+use tokio_core::reactor::Timeout;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{CacheControl, CacheDirective, ContentType};
+use response::Response;
+use util::json::json_string;
+use util::typemap::Key;
+
+type BoxCheck = Box<Fn(&Context) -> BoxFuture<(), String> + Send + Sync>;
+
+struct Check {
+    name: String,
+    liveness: bool,
+    run: BoxCheck,
+}
+
+/// The registered health checks of a server, and whether it is draining.
+///
+/// Clones share the same checks. See the [module documentation](index.html) for details.
+#[derive(Clone)]
+pub struct Health {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    checks: RwLock<Vec<Arc<Check>>>,
+    timeout: Mutex<Duration>,
+    draining: AtomicBool,
+}
+
+/// The outcome of a single check.
+struct Outcome {
+    name: String,
+    duration: Duration,
+    error: Option<String>,
+}
+
+impl Health {
+    /// Constructs a new `Health` without any checks.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                checks: RwLock::new(Vec::new()),
+                timeout: Mutex::new(Duration::from_secs(5)),
+                draining: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Registers a readiness check.
+    ///
+    /// The check is given the context of the probe request, and fails if the future it
+    /// returns resolves to an error, which is included in the report.
+    pub fn check<F, R>(&self, name: &str, check: F) -> &Self
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: 'static,
+        R::Error: Display,
+    {
+        self.add(name, false, check)
+    }
+
+    /// Registers a liveness check, which readiness also runs.
+    ///
+    /// Liveness checks should only fail when the process cannot recover by itself, as a
+    /// failed liveness probe usually gets it restarted.
+    pub fn liveness_check<F, R>(&self, name: &str, check: F) -> &Self
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: 'static,
+        R::Error: Display,
+    {
+        self.add(name, true, check)
+    }
+
+    /// Sets how long a check may take before it fails.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn timeout(&self, timeout: Duration) -> &Self {
+        *self.inner.timeout.lock().unwrap() = timeout;
+        self
+    }
+
+    /// Fail readiness from now on, as the server is shutting down.
+    ///
+    /// `Shio::run_until` calls this when a graceful shutdown begins.
+    pub fn drain(&self) {
+        self.inner.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the server is shutting down.
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    fn add<F, R>(&self, name: &str, liveness: bool, check: F) -> &Self
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: 'static,
+        R::Error: Display,
+    {
+        let run: BoxCheck = Box::new(move |ctx: &Context| {
+            check(ctx)
+                .into_future()
+                .map_err(|err| err.to_string())
+                .into_box()
+        });
+
+        self.inner.checks.write().unwrap().push(Arc::new(Check {
+            name: name.to_owned(),
+            liveness,
+            run,
+        }));
+
+        self
+    }
+
+    /// Run the checks of a probe, and respond with their report.
+    fn probe(&self, ctx: &Context, readiness: bool) -> BoxFuture<Response, hyper::Error> {
+        if readiness && self.is_draining() {
+            return future::ok(report(StatusCode::ServiceUnavailable, "draining", &[])).into_box();
+        }
+
+        let timeout = *self.inner.timeout.lock().unwrap();
+        // Checks are run outside the lock, so that one may register another
+        let checks = self.inner
+            .checks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|check| readiness || check.liveness)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let work = checks
+            .iter()
+            .map(|check| run(ctx, &check.name, (check.run)(ctx), timeout))
+            .collect::<Vec<_>>();
+
+        future::join_all(work)
+            .map(|outcomes| {
+                if outcomes.iter().all(|outcome| outcome.error.is_none()) {
+                    report(StatusCode::Ok, "pass", &outcomes)
+                } else {
+                    report(StatusCode::ServiceUnavailable, "fail", &outcomes)
+                }
+            })
+            .into_box()
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let checks = self.inner.checks.read().unwrap();
+
+        f.debug_struct("Health")
+            .field(
+                "checks",
+                &checks.iter().map(|check| &check.name).collect::<Vec<_>>(),
+            )
+            .field("draining", &self.is_draining())
+            .finish()
+    }
+}
+
+impl Key for Health {
+    type Value = Self;
+}
+
+/// Responds with the outcome of the liveness checks of the server.
+///
+/// See the [module documentation](index.html) for details.
+pub fn liveness(ctx: Context) -> BoxFuture<Response, hyper::Error> {
+    health(&ctx).probe(&ctx, false)
+}
+
+/// Responds with the outcome of every check of the server, or fails if it is draining.
+///
+/// See the [module documentation](index.html) for details.
+pub fn readiness(ctx: Context) -> BoxFuture<Response, hyper::Error> {
+    health(&ctx).probe(&ctx, true)
+}
+
+fn health(ctx: &Context) -> Health {
+    // A handler served without `Shio` has no checks
+    ctx.shared().try_get::<Health>().cloned().unwrap_or_default()
+}
+
+/// Run a single check, failing it if it takes longer than `timeout`.
+fn run(
+    ctx: &Context,
+    name: &str,
+    check: BoxFuture<(), String>,
+    timeout: Duration,
+) -> BoxFuture<Outcome, hyper::Error> {
+    let name = name.to_owned();
+    let start = Instant::now();
+
+    let result = match Timeout::new(timeout, ctx.handle()) {
+        Ok(timer) => check
+            .select2(timer)
+            .then(move |result| match result {
+                Ok(Either::A(((), _))) => Ok(()),
+                Ok(Either::B(_)) => Err(format!("timed out after {:?}", timeout)),
+                Err(Either::A((err, _))) => Err(err),
+                Err(Either::B((err, _))) => Err(err.to_string()),
+            })
+            .into_box(),
+
+        Err(err) => future::err(err.to_string()).into_box(),
+    };
+
+    result
+        .then(move |result| {
+            Ok(Outcome {
+                name,
+                duration: start.elapsed(),
+                error: result.err(),
+            })
+        })
+        .into_box()
+}
+
+fn report(status: StatusCode, summary: &str, outcomes: &[Outcome]) -> Response {
+    let mut body = format!("{{\"status\":{},\"checks\":{{", json_string(summary));
+
+    for (index, outcome) in outcomes.iter().enumerate() {
+        let millis = outcome.duration.as_secs() * 1000
+            + u64::from(outcome.duration.subsec_nanos()) / 1_000_000;
+
+        let _ = write!(
+            body,
+            "{}{}:{{\"status\":{},\"duration_ms\":{}",
+            if index == 0 { "" } else { "," },
+            json_string(&outcome.name),
+            json_string(if outcome.error.is_none() { "pass" } else { "fail" }),
+            millis
+        );
+
+        if let Some(ref error) = outcome.error {
+            let _ = write!(body, ",\"error\":{}", json_string(error));
+        }
+
+        body.push('}');
+    }
+
+    body.push_str("}}\n");
+
+    Response::build()
+        .status(status)
+        .header(ContentType::json())
+        .header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::future;
+    use regex::Regex;
+    use tokio_core::reactor::Timeout;
+
+    use Context;
+    use http::StatusCode;
+    use test::TestClient;
+    use super::{liveness, readiness, Health};
+
+    // Durations depend on the machine, so they are left out of comparisons
+    fn without_durations(body: &str) -> String {
+        Regex::new("\"duration_ms\":[0-9]+")
+            .unwrap()
+            .replace_all(body, "\"duration_ms\":_")
+            .into_owned()
+    }
+
+    #[test]
+    fn test_checks() {
+        let health = Health::new();
+        health
+            .liveness_check("loop", |_: &Context| future::ok::<(), String>(()))
+            .check("database", |_: &Context| Err::<(), _>("connection refused"));
+
+        let live = TestClient::new(liveness).manage::<Health>(health.clone());
+        let ready = TestClient::new(readiness).manage::<Health>(health.clone());
+
+        let response = live.get("/").send();
+        response.assert_status(StatusCode::Ok);
+        assert_eq!(
+            without_durations(response.text()),
+            "{\"status\":\"pass\",\"checks\":{\"loop\":{\"status\":\"pass\",\
+             \"duration_ms\":_}}}\n"
+        );
+
+        let response = ready.get("/").send();
+        response.assert_status(StatusCode::ServiceUnavailable);
+        assert_eq!(
+            without_durations(response.text()),
+            "{\"status\":\"fail\",\"checks\":{\"loop\":{\"status\":\"pass\",\
+             \"duration_ms\":_},\"database\":{\"status\":\"fail\",\
+             \"duration_ms\":_,\"error\":\"connection refused\"}}}\n"
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let health = Health::new();
+        health.timeout(Duration::from_millis(10)).check("slow", |ctx: &Context| {
+            Timeout::new(Duration::from_secs(10), ctx.handle()).unwrap()
+        });
+
+        let ready = TestClient::new(readiness).manage::<Health>(health.clone());
+        let response = ready.get("/").send();
+
+        response.assert_status(StatusCode::ServiceUnavailable);
+        assert!(response.text().contains("\"error\":\"timed out after 10ms\""));
+    }
+
+    #[test]
+    fn test_check_registers_check() {
+        let health = Health::new();
+        let registry = health.clone();
+        health.check("once", move |_: &Context| {
+            // Runs without the checks locked
+            registry.check("late", |_: &Context| Ok::<(), String>(()));
+            Ok::<(), String>(())
+        });
+
+        let ready = TestClient::new(readiness).manage::<Health>(health.clone());
+        ready.get("/").send().assert_status(StatusCode::Ok);
+
+        assert!(format!("{:?}", health).contains("\"late\""));
+    }
+
+    #[test]
+    fn test_draining() {
+        let health = Health::new();
+        let live = TestClient::new(liveness).manage::<Health>(health.clone());
+        let ready = TestClient::new(readiness).manage::<Health>(health.clone());
+
+        ready.get("/").send().assert_status(StatusCode::Ok);
+
+        health.drain();
+
+        live.get("/").send().assert_status(StatusCode::Ok);
+        ready
+            .get("/")
+            .send()
+            .assert_status(StatusCode::ServiceUnavailable)
+            .assert_body("{\"status\":\"draining\",\"checks\":{}}\n");
+
+        // Served without a `Health`, the probes have no checks to fail
+        let client = TestClient::new(readiness);
+        client.get("/").send().assert_status(StatusCode::Ok);
+    }
+}
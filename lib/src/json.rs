@@ -0,0 +1,236 @@
+//! JSON responses and request bodies, with [serde].
+//!
+//! This module is only available with the `serde` feature enabled.
+//!
+//! [serde]: https://serde.rs
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use futures::{Future, Stream};
+
+use Data;
+use errors;
+use ext::{BoxFuture, FutureExt};
+use response::{Responder, Response};
+use http::StatusCode;
+use http::header::{ContentLength, ContentType};
+
+// The largest body `Data::json` reads
+const LIMIT: usize = 1024 * 1024;
+
+/// A value that responds with its serialization as JSON.
+///
+/// The response has `Content-Type: application/json` and a `Content-Length`. If the value
+/// fails to serialize, the error is passed on and results in a 500.
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// # use shio::prelude::*;
+/// # use shio::json::Json;
+/// fn index(_: Context) -> Result<Response, shio::Error> {
+///     let mut user = BTreeMap::new();
+///     user.insert("name", "Ryan");
+///
+///     Response::with(Json(user))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+    /// Consumes the `Json`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize> Responder for Json<T> {
+    type Result = Result<Response, errors::Error>;
+
+    #[inline]
+    fn to_response(self) -> Self::Result {
+        let body = serde_json::to_vec(&self.0)?;
+
+        Ok(Response::build()
+            .header(ContentType::json())
+            .header(ContentLength(body.len() as u64))
+            .body(body))
+    }
+}
+
+/// An error that occurs while reading a JSON request body with `Data::json`.
+///
+/// Most of these are the client's fault; `status` gives the status to answer them with.
+#[derive(Debug)]
+pub enum Error {
+    /// The body could not be read.
+    Body(errors::Error),
+
+    /// The body was longer than the limit.
+    TooLarge,
+
+    /// The body was not JSON, or not of the expected shape.
+    Invalid(serde_json::Error),
+}
+
+impl Error {
+    /// Returns the status to answer the request with: `413 Payload Too Large` for a body over
+    /// the limit, and `400 Bad Request` otherwise.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            Error::TooLarge => StatusCode::PayloadTooLarge,
+            Error::Body(_) | Error::Invalid(_) => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Body(ref err) => err.fmt(f),
+            Error::TooLarge => f.write_str("request body too large"),
+            Error::Invalid(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Body(ref err) => err.description(),
+            Error::TooLarge => "request body too large",
+            Error::Invalid(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Body(ref err) => Some(err),
+            Error::TooLarge => None,
+            Error::Invalid(ref err) => Some(err),
+        }
+    }
+}
+
+impl Data {
+    /// Read the entire body, of up to 1 MiB, and deserialize it from JSON.
+    ///
+    /// ```rust
+    /// # use std::collections::BTreeMap;
+    /// # use shio::prelude::*;
+    /// # use shio::json::Json;
+    /// fn echo(ctx: Context) -> BoxFuture<Response, shio::Error> {
+    ///     ctx.data()
+    ///         .json::<BTreeMap<String, String>>()
+    ///         .then(|body| match body {
+    ///             Ok(body) => Response::with(Json(body)),
+    ///             // Answer a body that is too large or not valid with a 4xx
+    ///             Err(err) => Ok(Response::with(err.status())),
+    ///         })
+    ///         .into_box()
+    /// }
+    /// ```
+    pub fn json<T: DeserializeOwned + 'static>(self) -> BoxFuture<T, Error> {
+        self.json_with_limit(LIMIT)
+    }
+
+    /// Like `json`, but reads a body of up to `limit` bytes.
+    pub fn json_with_limit<T: DeserializeOwned + 'static>(
+        self,
+        limit: usize,
+    ) -> BoxFuture<T, Error> {
+        self.map_err(Error::Body)
+            .fold(Vec::new(), move |mut body, chunk| {
+                if body.len() + chunk.len() > limit {
+                    return Err(Error::TooLarge);
+                }
+
+                body.extend_from_slice(&chunk);
+                Ok(body)
+            })
+            .and_then(|body| serde_json::from_slice(&body).map_err(Error::Invalid))
+            .into_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use futures::Future;
+
+    use {Context, Data, Response};
+    use http::StatusCode;
+    use http::header::{ContentLength, ContentType};
+    use response::Responder;
+    use test::TestClient;
+    use super::Json;
+
+    #[test]
+    fn test_json_to_response() {
+        let client = TestClient::new(|_: Context| Json(vec![1, 2, 3]).to_response());
+
+        client
+            .get("/")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header(&ContentType::json())
+            .assert_header(&ContentLength(7))
+            .assert_body("[1,2,3]");
+    }
+
+    #[test]
+    fn test_json_serialize_error() {
+        // JSON object keys must be strings
+        let client = TestClient::new(|_: Context| -> Result<Response, ::Error> {
+            let mut map = BTreeMap::new();
+            map.insert(vec![1], 1);
+
+            Response::with(Json(map))
+        });
+
+        client
+            .get("/")
+            .send()
+            .assert_status(StatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn test_data_json() {
+        let data = Data::from(r#"{"name": "shio"}"#);
+        let body = data.json::<BTreeMap<String, String>>().wait().unwrap();
+
+        assert_eq!(body["name"], "shio");
+    }
+
+    #[test]
+    fn test_data_json_invalid() {
+        let err = Data::from("{").json::<Vec<u8>>().wait().unwrap_err();
+        assert_eq!(err.status(), StatusCode::BadRequest);
+
+        let err = Data::from("[1, 2, 3]")
+            .json_with_limit::<Vec<u8>>(4)
+            .wait()
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::PayloadTooLarge);
+    }
+}
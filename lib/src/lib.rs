@@ -2,22 +2,39 @@
 #![cfg_attr(feature = "cargo-clippy", allow(missing_docs_in_private_items, stutter))]
 #![cfg_attr(feature = "nightly", feature(specialization))]
 
+extern crate base64;
+#[cfg(feature = "brotli")]
+extern crate brotli;
+extern crate bytes;
+extern crate flate2;
+#[macro_use]
 extern crate futures;
+extern crate futures_cpupool;
 extern crate http as http_types;
 extern crate hyper;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 extern crate net2;
 extern crate num_cpus;
+extern crate rand;
 extern crate regex;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+extern crate sha1;
 extern crate tokio_core;
+extern crate tokio_io;
+extern crate unicase;
 extern crate unsafe_any;
 
 pub mod state;
 pub mod context;
 mod handler;
 mod shio;
-mod service;
+pub mod service;
 pub mod ext;
 pub mod response;
 pub mod request;
@@ -26,6 +43,18 @@ pub mod router;
 pub mod util;
 pub mod data;
 pub mod http;
+pub mod pool;
+pub mod client;
+pub mod test;
+pub mod sse;
+pub mod websocket;
+pub mod files;
+pub mod health;
+pub mod middleware;
+pub mod metrics;
+pub mod trace;
+#[cfg(feature = "serde")]
+pub mod json;
 
 pub use response::Response;
 pub use request::Request;
@@ -0,0 +1,425 @@
+//! Request metrics in the Prometheus text format.
+//!
+//! A [`Metrics`] registry is shared by every worker thread. Used as [`Middleware`] it counts
+//! requests and their latency, labeled by method, route pattern, and status; given to
+//! `Shio::metrics` it also counts open connections and worker respawns. The handler returned
+//! by `Metrics::exporter` serves everything recorded so far.
+//!
+//! ```rust,no_run
+//! # use shio::prelude::*;
+//! # use shio::metrics::Metrics;
+//! # use shio::middleware::Stack;
+//! let metrics = Metrics::new();
+//!
+//! let mut router = shio::router::Router::new();
+//! router.add((Method::GET, "/", |_| Response::with("Hello World\n")));
+//! router.add((Method::GET, "/metrics", metrics.exporter()));
+//!
+//! Shio::new(Stack::new(router).with(metrics.clone()))
+//!     .metrics(&metrics)
+//!     .run(":7878")
+//!     .unwrap();
+//! ```
+//!
+//! | Metric                               | Type      | Labels                      |
+//! |--------------------------------------|-----------|-----------------------------|
+//! | `shio_http_requests_total`           | counter   | `method`, `route`, `status` |
+//! | `shio_http_request_duration_seconds` | histogram | `method`, `route`, `status` |
+//! | `shio_http_requests_in_flight`       | gauge     |                             |
+//! | `shio_http_connections_open`         | gauge     |                             |
+//! | `shio_worker_respawns_total`         | counter   |                             |
+//!
+//! Requests that no route matched are labeled with a `route` of `unmatched`.
+//!
+//! [`Metrics`]: struct.Metrics.html
+//! [`Middleware`]: ../middleware/trait.Middleware.html
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use hyper;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use handler::Handler;
+use http::header::ContentType;
+use middleware::{Middleware, Next};
+use response::Response;
+use router::RouteSlot;
+
+// The default upper bounds of the latency buckets, in seconds
+const BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// A registry of request metrics, shared by every clone.
+///
+/// See the [module documentation](index.html) for the metrics recorded.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    buckets: Vec<f64>,
+    requests: Mutex<BTreeMap<Labels, Series>>,
+    in_flight: AtomicUsize,
+    connections: AtomicUsize,
+    respawns: AtomicUsize,
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Labels {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+/// The count and latency histogram of requests with the same labels.
+struct Series {
+    // Cumulative counts of requests that took at most each bucket's upper bound
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    /// Constructs a new, empty `Metrics` with the default latency buckets.
+    pub fn new() -> Self {
+        Self::with_buckets(BUCKETS.to_vec())
+    }
+
+    /// Constructs a new, empty `Metrics` with latency buckets of the given upper bounds, in
+    /// seconds.
+    ///
+    /// # Panics
+    ///
+    /// If a bound is NaN.
+    pub fn with_buckets(mut buckets: Vec<f64>) -> Self {
+        assert!(
+            !buckets.iter().any(|bound| bound.is_nan()),
+            "bucket bounds must not be NaN"
+        );
+
+        buckets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        buckets.dedup();
+
+        Self {
+            inner: Arc::new(Inner {
+                buckets,
+                requests: Mutex::new(BTreeMap::new()),
+                in_flight: AtomicUsize::new(0),
+                connections: AtomicUsize::new(0),
+                respawns: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Returns a [`Handler`] that responds with the metrics in the Prometheus text format.
+    ///
+    /// [`Handler`]: ../trait.Handler.html
+    pub fn exporter(&self) -> Exporter {
+        Exporter {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    pub(crate) fn connection(&self) -> Gauge {
+        Gauge::new(self.inner.clone(), |inner| &inner.connections)
+    }
+
+    pub(crate) fn respawned(&self) {
+        self.inner.respawns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record(&self, labels: Labels, elapsed: Duration) {
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let buckets = &self.inner.buckets;
+
+        let mut requests = self.inner.requests.lock().unwrap();
+        let series = requests.entry(labels).or_insert_with(|| Series {
+            buckets: vec![0; buckets.len()],
+            count: 0,
+            sum: 0.0,
+        });
+
+        for (count, &bound) in series.buckets.iter_mut().zip(buckets) {
+            if seconds <= bound {
+                *count += 1;
+            }
+        }
+
+        series.count += 1;
+        series.sum += seconds;
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let requests = self.inner.requests.lock().unwrap();
+
+        out.push_str("# HELP shio_http_requests_total Total HTTP requests handled.\n");
+        out.push_str("# TYPE shio_http_requests_total counter\n");
+        for (labels, series) in requests.iter() {
+            let _ = writeln!(out, "shio_http_requests_total{{{}}} {}", labels, series.count);
+        }
+
+        out.push_str(
+            "# HELP shio_http_request_duration_seconds Time taken to respond to HTTP requests.\n",
+        );
+        out.push_str("# TYPE shio_http_request_duration_seconds histogram\n");
+        for (labels, series) in requests.iter() {
+            for (count, bound) in series.buckets.iter().zip(&self.inner.buckets) {
+                let _ = writeln!(
+                    out,
+                    "shio_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "shio_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, series.count
+            );
+            let _ = writeln!(
+                out,
+                "shio_http_request_duration_seconds_sum{{{}}} {}",
+                labels, series.sum
+            );
+            let _ = writeln!(
+                out,
+                "shio_http_request_duration_seconds_count{{{}}} {}",
+                labels, series.count
+            );
+        }
+
+        drop(requests);
+
+        let gauges = [
+            (
+                "shio_http_requests_in_flight",
+                "gauge",
+                "HTTP requests currently being handled.",
+                &self.inner.in_flight,
+            ),
+            (
+                "shio_http_connections_open",
+                "gauge",
+                "HTTP connections currently open.",
+                &self.inner.connections,
+            ),
+            (
+                "shio_worker_respawns_total",
+                "counter",
+                "Worker threads respawned after a panic.",
+                &self.inner.respawns,
+            ),
+        ];
+
+        for &(name, kind, help, value) in &gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ::std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Metrics")
+            .field("buckets", &self.inner.buckets)
+            .finish()
+    }
+}
+
+impl Middleware for Metrics {
+    fn call(&self, mut ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let in_flight = Gauge::new(self.inner.clone(), |inner| &inner.in_flight);
+        let start = Instant::now();
+        let method = method_label(ctx.method());
+
+        let slot = RouteSlot::install(&mut ctx);
+
+        let metrics = self.clone();
+
+        next.call(ctx)
+            .map(move |response| {
+                let labels = Labels {
+                    method,
+                    route: slot.get()
+                        .map_or_else(|| "unmatched".to_owned(), |route| route.to_string()),
+                    status: response.status().into(),
+                };
+
+                metrics.record(labels, start.elapsed());
+                drop(in_flight);
+
+                response
+            })
+            .into_box()
+    }
+}
+
+impl ::std::fmt::Display for Labels {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "method=\"{}\",route=\"{}\",status=\"{}\"",
+            escape(&self.method),
+            escape(&self.route),
+            self.status
+        )
+    }
+}
+
+/// The `method` label of a request.
+///
+/// Extension methods are counted together, as clients may send any number of them.
+fn method_label(method: &hyper::Method) -> String {
+    match *method {
+        hyper::Method::Extension(_) => "other".to_owned(),
+        ref method => method.to_string(),
+    }
+}
+
+/// Escape a label value for the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A [`Handler`] that responds with the metrics of a [`Metrics`] registry.
+///
+/// [`Handler`]: ../trait.Handler.html
+/// [`Metrics`]: struct.Metrics.html
+#[derive(Clone, Debug)]
+pub struct Exporter {
+    metrics: Metrics,
+}
+
+impl Handler for Exporter {
+    type Result = Response;
+
+    fn call(&self, _: Context) -> Self::Result {
+        Response::build()
+            .header(ContentType(
+                "text/plain; version=0.0.4; charset=utf-8".parse().unwrap(),
+            ))
+            .body(self.metrics.render())
+    }
+}
+
+/// Increments a gauge while alive, and decrements it when dropped.
+pub(crate) struct Gauge {
+    inner: Arc<Inner>,
+    value: fn(&Inner) -> &AtomicUsize,
+}
+
+impl Gauge {
+    fn new(inner: Arc<Inner>, value: fn(&Inner) -> &AtomicUsize) -> Self {
+        value(&inner).fetch_add(1, Ordering::Relaxed);
+
+        Self { inner, value }
+    }
+}
+
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        (self.value)(&self.inner).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper;
+
+    use {Context, Response};
+    use http::{Method, StatusCode};
+    use middleware::Stack;
+    use router::Router;
+    use test::TestClient;
+    use super::{method_label, Labels, Metrics};
+
+    #[test]
+    fn test_record() {
+        let metrics = Metrics::with_buckets(vec![1.0, 0.1]);
+        let labels = Labels {
+            method: "GET".into(),
+            route: "/user/{id}".into(),
+            status: 200,
+        };
+
+        metrics.record(labels.clone(), Duration::from_millis(50));
+        metrics.record(labels, Duration::from_millis(500));
+
+        let rendered = metrics.render();
+        let labels = "method=\"GET\",route=\"/user/{id}\",status=\"200\"";
+
+        for line in &[
+            format!("shio_http_requests_total{{{}}} 2", labels),
+            format!("shio_http_request_duration_seconds_bucket{{{},le=\"0.1\"}} 1", labels),
+            format!("shio_http_request_duration_seconds_bucket{{{},le=\"1\"}} 2", labels),
+            format!("shio_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2", labels),
+            format!("shio_http_request_duration_seconds_sum{{{}}} 0.55", labels),
+            "shio_http_requests_in_flight 0".to_owned(),
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {:?} in\n{}", line, rendered);
+        }
+    }
+
+    #[test]
+    fn test_method_label() {
+        assert_eq!(method_label(&hyper::Method::Delete), "DELETE");
+        assert_eq!(method_label(&hyper::Method::Extension("PURGE".into())), "other");
+    }
+
+    #[test]
+    #[should_panic(expected = "bucket bounds must not be NaN")]
+    fn test_nan_bucket() {
+        Metrics::with_buckets(vec![0.1, ::std::f64::NAN]);
+    }
+
+    #[test]
+    fn test_route_labels() {
+        let metrics = Metrics::new();
+
+        let mut router = Router::new();
+        router.add((Method::GET, "/user/{id}", |_: Context| Response::new()));
+        router.add((Method::GET, "/metrics", metrics.exporter()));
+
+        let client = TestClient::new(Stack::new(router).with(metrics.clone()));
+
+        client.get("/user/1").send();
+        client.get("/user/2").send();
+        client.get("/missing").send().assert_status(StatusCode::NotFound);
+
+        let response = client.get("/metrics").send();
+        let body = response.text();
+
+        assert!(body.contains(
+            "shio_http_requests_total{method=\"GET\",route=\"/user/{id}\",status=\"200\"} 2\n"
+        ));
+        assert!(body.contains(
+            "shio_http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1\n"
+        ));
+
+        // The request for the metrics is still in flight while they are rendered
+        assert!(body.contains("shio_http_requests_in_flight 1\n"));
+    }
+}
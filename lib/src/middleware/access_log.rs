@@ -0,0 +1,457 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::{Async, Future, Poll, Stream};
+use hyper::{self, Chunk, HttpVersion};
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{Referer, UserAgent};
+use response::{Body, Response};
+use util::json::json_string;
+use super::{Middleware, Next, RequestId};
+
+/// The format of the lines written by [`AccessLog`].
+///
+/// [`AccessLog`]: struct.AccessLog.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// The Common Log Format:
+    ///
+    /// `127.0.0.1 - - [01/Feb/2018:12:00:00 +0000] "GET / HTTP/1.1" 200 11`
+    Common,
+
+    /// The Combined Log Format, which adds the referer and user agent to `Common`:
+    ///
+    /// `127.0.0.1 - - [01/Feb/2018:12:00:00 +0000] "GET / HTTP/1.1" 200 11 "-" "curl/7.58.0"`
+    Combined,
+
+    /// One JSON object per line, with every recorded field including the duration in
+    /// milliseconds and the request ID.
+    Json,
+}
+
+/// [`Middleware`] that logs a line for every request once its response has been sent.
+///
+/// Lines are written through the `log` crate at the `info` level with the target
+/// `shio::access`, which may be changed with `AccessLog::target`. The request ID is the
+/// [`RequestId`] in the request state, or else in the `X-Request-Id` header of the request
+/// or response.
+///
+/// The size recorded is the number of bytes of the body actually sent, so a streaming body is
+/// counted as it is written. A response that is abandoned part way, such as when the client
+/// disconnects, is logged with the bytes sent until then.
+///
+/// ```rust,no_run
+/// # use shio::prelude::*;
+/// # use shio::middleware::{AccessLog, Stack};
+/// # use shio::middleware::access_log::Format;
+/// # let router = shio::router::Router::new();
+/// Shio::new(Stack::new(router).with(AccessLog::new().format(Format::Json)))
+///     .run(":7878")
+///     .unwrap();
+/// ```
+///
+/// [`Middleware`]: trait.Middleware.html
+/// [`RequestId`]: struct.RequestId.html
+#[derive(Clone, Debug)]
+pub struct AccessLog {
+    format: Format,
+    target: Arc<str>,
+}
+
+impl AccessLog {
+    /// Constructs a new `AccessLog`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the format of each line.
+    ///
+    /// Defaults to `Format::Combined`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the target lines are logged to.
+    ///
+    /// Defaults to `shio::access`.
+    pub fn target<S: Into<String>>(mut self, target: S) -> Self {
+        self.target = Arc::from(target.into());
+        self
+    }
+
+    fn write(&self, entry: &Entry) {
+        info!(target: &self.target, "{}", entry.format(self.format));
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self {
+            format: Format::Combined,
+            target: Arc::from("shio::access"),
+        }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let headers = ctx.headers();
+        let mut entry = Entry {
+            time: SystemTime::now(),
+            remote_addr: ctx.remote_addr(),
+            method: ctx.method().to_string(),
+            uri: ctx.uri().to_string(),
+            version: *ctx.version(),
+            status: StatusCode::Ok,
+            bytes: 0,
+            duration: Duration::from_secs(0),
+            referer: headers.get::<Referer>().map(|referer| referer.to_string()),
+            user_agent: headers.get::<UserAgent>().map(|agent| agent.to_string()),
+            request_id: ctx.try_get::<RequestId>()
+                .or_else(|| headers.get::<RequestId>())
+                .map(|id| id.to_string()),
+        };
+
+        let start = Instant::now();
+        let log = self.clone();
+
+        next.call(ctx)
+            .map(move |mut response| {
+                entry.status = response.status();
+
+                // `RequestIds` may run inside this middleware and only be seen in the response
+                if entry.request_id.is_none() {
+                    entry.request_id = response
+                        .headers()
+                        .get::<RequestId>()
+                        .map(|id| id.to_string());
+                }
+
+                let body = response.take_body();
+                response.set_body(Body::wrap_stream(LoggedBody {
+                    body,
+                    entry: Some(entry),
+                    start,
+                    log,
+                }));
+
+                response
+            })
+            .into_box()
+    }
+}
+
+/// The details of a request recorded in the access log.
+#[derive(Debug)]
+struct Entry {
+    time: SystemTime,
+    remote_addr: Option<SocketAddr>,
+    method: String,
+    uri: String,
+    version: HttpVersion,
+    status: StatusCode,
+    bytes: u64,
+    duration: Duration,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+}
+
+impl Entry {
+    fn format(&self, format: Format) -> String {
+        let mut line = String::new();
+
+        if format == Format::Json {
+            line.push('{');
+            let _ = write!(line, "\"time\":{}", json_string(&rfc3339(self.time)));
+            let _ = write!(line, ",\"remote_addr\":{}", json_option(&self.remote_addr));
+            let _ = write!(line, ",\"method\":{}", json_string(&self.method));
+            let _ = write!(line, ",\"path\":{}", json_string(&self.uri));
+            let _ = write!(line, ",\"version\":{}", json_string(&self.version.to_string()));
+            let _ = write!(line, ",\"status\":{}", u16::from(self.status));
+            let _ = write!(line, ",\"bytes\":{}", self.bytes);
+            let _ = write!(line, ",\"duration_ms\":{:.3}", millis(self.duration));
+            let _ = write!(line, ",\"referer\":{}", json_option(&self.referer));
+            let _ = write!(line, ",\"user_agent\":{}", json_option(&self.user_agent));
+            let _ = write!(line, ",\"request_id\":{}", json_option(&self.request_id));
+            line.push('}');
+
+            return line;
+        }
+
+        let _ = write!(
+            line,
+            "{} - - [{}] \"{} {} {}\" {} ",
+            self.remote_addr
+                .map_or_else(|| "-".to_owned(), |addr| addr.ip().to_string()),
+            clf_time(self.time),
+            self.method,
+            quoted(&self.uri),
+            self.version,
+            u16::from(self.status),
+        );
+
+        if self.bytes == 0 {
+            line.push('-');
+        } else {
+            let _ = write!(line, "{}", self.bytes);
+        }
+
+        if format == Format::Combined {
+            let _ = write!(
+                line,
+                " \"{}\" \"{}\"",
+                self.referer.as_ref().map_or("-".into(), |referer| quoted(referer)),
+                self.user_agent.as_ref().map_or("-".into(), |agent| quoted(agent)),
+            );
+        }
+
+        line
+    }
+}
+
+/// Counts the bytes of `body` as they are sent, and logs the entry once the body ends.
+struct LoggedBody {
+    body: Body,
+    // `None` once the entry has been logged
+    entry: Option<Entry>,
+    start: Instant,
+    log: AccessLog,
+}
+
+impl LoggedBody {
+    fn finish(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.duration = self.start.elapsed();
+            self.log.write(&entry);
+        }
+    }
+}
+
+impl Stream for LoggedBody {
+    type Item = Chunk;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+        match self.body.poll() {
+            Ok(Async::Ready(Some(chunk))) => {
+                if let Some(ref mut entry) = self.entry {
+                    entry.bytes += chunk.len() as u64;
+                }
+
+                Ok(Async::Ready(Some(chunk)))
+            }
+
+            Ok(Async::Ready(None)) => {
+                self.finish();
+                Ok(Async::Ready(None))
+            }
+
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+
+            Err(err) => {
+                self.finish();
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        // The body was abandoned before it ended, or was never sent at all
+        self.finish();
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e3 + f64::from(duration.subsec_nanos()) / 1e6
+}
+
+/// Escape `"` and `\` so a value can't break out of a quoted field of a log line.
+fn quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn json_option<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "null".to_owned(), |value| json_string(&value.to_string()))
+}
+
+/// The UTC date and time of `time`, as `(year, month, day, hour, minute, second)`.
+fn civil(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let (days, secs) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Days since the epoch to a proleptic Gregorian date, from Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (year, month, day, hour, minute, second) = civil(time);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil(time);
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use futures::stream;
+    use log::{self, Log, Metadata, Record};
+
+    use {Context, Response};
+    use http::StatusCode;
+    use http::header::UserAgent;
+    use response::Body;
+    use test::TestClient;
+    use super::super::Stack;
+    use super::{clf_time, AccessLog, Entry, Format};
+
+    struct Capture(Mutex<Vec<(String, String)>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let line = (record.target().to_owned(), record.args().to_string());
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+    fn capture() {
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Info);
+    }
+
+    /// Lines logged so far to `target`.
+    fn captured(target: &str) -> Vec<String> {
+        CAPTURE
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&&(ref line_target, _)| line_target == target)
+            .map(|&(_, ref line)| line.clone())
+            .collect()
+    }
+
+    fn entry() -> Entry {
+        Entry {
+            time: UNIX_EPOCH + Duration::from_secs(1_517_486_400),
+            remote_addr: Some("127.0.0.1:4000".parse().unwrap()),
+            method: "GET".into(),
+            uri: "/search?q=\"shio\"".into(),
+            version: Default::default(),
+            status: StatusCode::Ok,
+            bytes: 11,
+            duration: Duration::from_millis(5),
+            referer: None,
+            user_agent: Some("curl/7.58.0".into()),
+            request_id: Some("abc".into()),
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            entry().format(Format::Common),
+            "127.0.0.1 - - [01/Feb/2018:12:00:00 +0000] \"GET /search?q=\\\"shio\\\" HTTP/1.1\" \
+             200 11"
+        );
+
+        assert_eq!(
+            entry().format(Format::Combined),
+            "127.0.0.1 - - [01/Feb/2018:12:00:00 +0000] \"GET /search?q=\\\"shio\\\" HTTP/1.1\" \
+             200 11 \"-\" \"curl/7.58.0\""
+        );
+
+        assert_eq!(
+            entry().format(Format::Json),
+            "{\"time\":\"2018-02-01T12:00:00Z\",\"remote_addr\":\"127.0.0.1:4000\",\
+             \"method\":\"GET\",\"path\":\"/search?q=\\\"shio\\\"\",\"version\":\"HTTP/1.1\",\
+             \"status\":200,\"bytes\":11,\"duration_ms\":5.000,\"referer\":null,\
+             \"user_agent\":\"curl/7.58.0\",\"request_id\":\"abc\"}"
+        );
+    }
+
+    #[test]
+    fn test_clf_time() {
+        assert_eq!(clf_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(
+            clf_time(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+            "29/Feb/2000:12:34:56 +0000"
+        );
+    }
+
+    #[test]
+    fn test_streaming_body() {
+        capture();
+
+        let handler = |_: Context| {
+            let chunks = vec!["Hello", " ", "World"].into_iter().map(Ok::<_, ::hyper::Error>);
+
+            Response::build().body(Body::wrap_stream(stream::iter_result(chunks)))
+        };
+
+        let log = AccessLog::new().format(Format::Common).target("test::streaming");
+        let client = TestClient::new(Stack::new(handler).with(log));
+
+        client
+            .get("/hello")
+            .remote_addr("10.0.0.1:5000".parse().unwrap())
+            .header(UserAgent::new("test"))
+            .send()
+            .assert_body("Hello World");
+
+        let lines = captured("test::streaming");
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("10.0.0.1 - - ["));
+        assert!(lines[0].ends_with("] \"GET /hello HTTP/1.1\" 200 11"));
+    }
+}
@@ -0,0 +1,444 @@
+use std::io::{self, Write};
+use std::mem;
+
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::{Async, Future, Poll, Stream};
+use hyper::{self, Chunk, Method};
+
+#[cfg(feature = "brotli")]
+use brotli::CompressorWriter;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{q, AcceptEncoding, CacheControl, CacheDirective, ContentEncoding, ContentLength,
+                   ContentRange, ContentType, ETag, Encoding, EntityTag, Headers, Quality};
+use response::{Body, Response};
+use super::{add_vary, Middleware, Next};
+
+// Compressed output is sent once at least this much has accumulated, even if more of the
+// body is ready to be compressed
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// [`Middleware`] that compresses response bodies with an encoding the client accepts.
+///
+/// `gzip` and `deflate` are always supported, and `br` when the `brotli` feature is enabled.
+/// Streaming bodies are compressed as they are written; whatever has been compressed is
+/// flushed to the client whenever the body has no more data ready.
+///
+/// Responses that are already encoded, have a media type that is already compressed (such
+/// as images other than SVG, audio, and video), or have a `Content-Length` below
+/// `Compress::min_size` are sent as they are.
+///
+/// [`Middleware`]: trait.Middleware.html
+#[derive(Clone, Debug)]
+pub struct Compress {
+    min_size: u64,
+}
+
+impl Compress {
+    /// Constructs a new `Compress`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the smallest `Content-Length`, in bytes, of a body that is compressed.
+    ///
+    /// Defaults to `1024`. Bodies without a `Content-Length` are always compressed.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    fn should_compress(&self, response: &Response) -> bool {
+        let status = response.status();
+        if status.is_informational() || status == StatusCode::NoContent
+            || status == StatusCode::NotModified
+        {
+            return false;
+        }
+
+        let headers = response.headers();
+        if headers.has::<ContentEncoding>() || headers.has::<ContentRange>() {
+            return false;
+        }
+
+        if let Some(&CacheControl(ref directives)) = headers.get::<CacheControl>() {
+            if directives.contains(&CacheDirective::NoTransform) {
+                return false;
+            }
+        }
+
+        if let Some(&ContentType(ref mime)) = headers.get::<ContentType>() {
+            if !is_compressible(mime.type_().as_str(), mime.subtype().as_str()) {
+                return false;
+            }
+        }
+
+        match headers.get::<ContentLength>() {
+            Some(&ContentLength(len)) => len >= self.min_size,
+            None => true,
+        }
+    }
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Self { min_size: 1024 }
+    }
+}
+
+impl Middleware for Compress {
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let encoding = if *ctx.method() == Method::Head {
+            None
+        } else {
+            negotiate(ctx.headers())
+        };
+
+        let compress = self.clone();
+
+        next.call(ctx)
+            .map(move |mut response| {
+                add_vary(response.headers_mut(), "Accept-Encoding");
+
+                let encoding = match encoding {
+                    Some(ref encoding) if compress.should_compress(&response) => encoding,
+                    _ => return response,
+                };
+
+                let etag = match response.headers().get::<ETag>() {
+                    // The compressed body is no longer byte-for-byte the same entity
+                    Some(&ETag(ref tag)) if !tag.weak => {
+                        Some(ETag(EntityTag::weak(tag.tag().to_owned())))
+                    }
+
+                    _ => None,
+                };
+
+                {
+                    let headers = response.headers_mut();
+                    headers.remove::<ContentLength>();
+                    headers.set(ContentEncoding(vec![encoding.clone()]));
+
+                    if let Some(etag) = etag {
+                        headers.set(etag);
+                    }
+                }
+
+                let body = response.take_body();
+                response.set_body(Body::wrap_stream(CompressStream {
+                    body,
+                    encoder: Some(Encoder::new(encoding)),
+                    pending: false,
+                }));
+
+                response
+            })
+            .into_box()
+    }
+}
+
+/// Whether a body of this media type is likely to get smaller when compressed.
+fn is_compressible(type_: &str, subtype: &str) -> bool {
+    match (type_, subtype) {
+        ("image", "svg+xml") => true,
+        ("image", _) | ("audio", _) | ("video", _) => false,
+        ("font", "woff") | ("font", "woff2") => false,
+        ("application", "zip") | ("application", "gzip") | ("application", "x-gzip")
+        | ("application", "x-brotli") | ("application", "octet-stream") => false,
+        _ => true,
+    }
+}
+
+/// Choose the supported encoding the client prefers, if any.
+fn negotiate(headers: &Headers) -> Option<Encoding> {
+    let accepted = headers.get::<AcceptEncoding>()?;
+    let wildcard = Encoding::EncodingExt("*".into());
+
+    let quality = |encoding: &Encoding| -> Quality {
+        accepted
+            .iter()
+            .find(|item| item.item == *encoding)
+            .or_else(|| accepted.iter().find(|item| item.item == wildcard))
+            .map_or(q(0), |item| item.quality)
+    };
+
+    // Listed from most to least preferred when the client has no preference
+    let mut supported = Vec::with_capacity(3);
+    if cfg!(feature = "brotli") {
+        supported.push(Encoding::Brotli);
+    }
+
+    supported.push(Encoding::Gzip);
+    supported.push(Encoding::Deflate);
+
+    let mut best: Option<(Encoding, Quality)> = None;
+    for encoding in supported {
+        let quality = quality(&encoding);
+        if quality > q(0) && best.as_ref().map_or(true, |&(_, best)| quality > best) {
+            best = Some((encoding, quality));
+        }
+    }
+
+    best.map(|(encoding, _)| encoding)
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(encoding: &Encoding) -> Self {
+        match *encoding {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                // Quality 4 compresses about as well as gzip, and much faster than the
+                // default of 11 which is meant for content compressed ahead of time
+                Encoder::Brotli(Box::new(CompressorWriter::new(Vec::new(), 4096, 4, 22)))
+            }
+
+            Encoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))
+            }
+
+            _ => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+
+    /// The compressed output written so far.
+    fn output(&mut self) -> &mut Vec<u8> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => encoder.get_mut(),
+            Encoder::Deflate(ref mut encoder) => encoder.get_mut(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(ref mut encoder) => encoder.get_mut(),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => encoder.write(buf),
+            Encoder::Deflate(ref mut encoder) => encoder.write(buf),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Encoder::Gzip(ref mut encoder) => encoder.flush(),
+            Encoder::Deflate(ref mut encoder) => encoder.flush(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Compresses the chunks of `body` as they become ready.
+struct CompressStream {
+    body: Body,
+    // `None` once the encoder has been finished
+    encoder: Option<Encoder>,
+    // Whether input has been written to the encoder since it was last flushed
+    pending: bool,
+}
+
+impl Stream for CompressStream {
+    type Item = Chunk;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+        loop {
+            let mut encoder = match self.encoder.take() {
+                Some(encoder) => encoder,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            match self.body.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    encoder.write_all(&chunk)?;
+                    self.pending = true;
+
+                    // Until there is enough output to be worth sending, keep compressing
+                    let output = if encoder.output().len() >= CHUNK_SIZE {
+                        Some(mem::replace(encoder.output(), Vec::new()))
+                    } else {
+                        None
+                    };
+
+                    self.encoder = Some(encoder);
+
+                    if let Some(output) = output {
+                        return Ok(Async::Ready(Some(output.into())));
+                    }
+                }
+
+                Ok(Async::Ready(None)) => {
+                    return Ok(Async::Ready(Some(encoder.finish()?.into())));
+                }
+
+                Ok(Async::NotReady) => {
+                    // Nothing more is ready to compress, so send what has been written so
+                    // far; this keeps streamed responses such as event streams live
+                    if self.pending {
+                        encoder.flush()?;
+                        self.pending = false;
+                    }
+
+                    let output = mem::replace(encoder.output(), Vec::new());
+                    self.encoder = Some(encoder);
+
+                    return Ok(if output.is_empty() {
+                        Async::NotReady
+                    } else {
+                        Async::Ready(Some(output.into()))
+                    });
+                }
+
+                Err(err) => {
+                    self.encoder = Some(encoder);
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use futures::stream;
+
+    use {Context, Response};
+    use http::StatusCode;
+    use http::header::{q, AcceptEncoding, ContentEncoding, ContentLength, ContentType, Encoding,
+                       Headers, QualityItem, Vary, qitem};
+    use response::Body;
+    use test::TestClient;
+    use super::super::Stack;
+    use super::{negotiate, Compress};
+
+    fn text(_: Context) -> Response {
+        Response::build()
+            .header(ContentType::plaintext())
+            .header(ContentLength(2048))
+            .body(vec![b'a'; 2048])
+    }
+
+    fn vary() -> Vary {
+        Vary::Items(vec!["Accept-Encoding".parse().unwrap()])
+    }
+
+    #[test]
+    fn test_gzip() {
+        let client = TestClient::new(Stack::new(text).with(Compress::new()));
+        let response = client
+            .get("/")
+            .header(AcceptEncoding(vec![qitem(Encoding::Gzip)]))
+            .send();
+
+        response
+            .assert_status(StatusCode::Ok)
+            .assert_header(&ContentEncoding(vec![Encoding::Gzip]))
+            .assert_header(&vary());
+
+        assert!(response.header::<ContentLength>().map_or(true, |len| len.0 < 2048));
+
+        let mut body = Vec::new();
+        GzDecoder::new(response.body()).read_to_end(&mut body).unwrap();
+        assert_eq!(body, vec![b'a'; 2048]);
+    }
+
+    #[test]
+    fn test_deflate_stream() {
+        let handler = |_: Context| {
+            let chunks = vec!["Hello", " ", "World"].into_iter().map(Ok::<_, ::hyper::Error>);
+
+            Response::build().body(Body::wrap_stream(stream::iter_result(chunks)))
+        };
+
+        let client = TestClient::new(Stack::new(handler).with(Compress::new()));
+        let response = client
+            .get("/")
+            .header(AcceptEncoding(vec![qitem(Encoding::Deflate)]))
+            .send();
+
+        response.assert_header(&ContentEncoding(vec![Encoding::Deflate]));
+
+        let mut body = String::new();
+        ZlibDecoder::new(response.body()).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "Hello World");
+    }
+
+    #[test]
+    fn test_skip() {
+        let client = TestClient::new(Stack::new(text).with(Compress::new().min_size(4096)));
+
+        client
+            .get("/")
+            .header(AcceptEncoding(vec![qitem(Encoding::Gzip)]))
+            .send()
+            .assert_header(&vary())
+            .assert_header(&ContentLength(2048));
+
+        let client = TestClient::new(Stack::new(text).with(Compress::new()));
+        let response = client.get("/").send();
+
+        response.assert_header(&ContentLength(2048));
+        assert!(response.header::<ContentEncoding>().is_none());
+
+        let image = |_: Context| {
+            Response::build()
+                .header(ContentType::png())
+                .body(vec![0; 2048])
+        };
+
+        let client = TestClient::new(Stack::new(image).with(Compress::new()));
+        let response = client
+            .get("/")
+            .header(AcceptEncoding(vec![qitem(Encoding::Gzip)]))
+            .send();
+
+        assert!(response.header::<ContentEncoding>().is_none());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let mut headers = Headers::new();
+        assert_eq!(negotiate(&headers), None);
+
+        headers.set(AcceptEncoding(vec![
+            QualityItem::new(Encoding::Gzip, q(0.5)),
+            qitem(Encoding::Deflate),
+        ]));
+        assert_eq!(negotiate(&headers), Some(Encoding::Deflate));
+
+        headers.set(AcceptEncoding(vec![
+            QualityItem::new(Encoding::Gzip, q(0)),
+            QualityItem::new(Encoding::EncodingExt("*".into()), q(0.1)),
+        ]));
+        let encoding = negotiate(&headers);
+        assert!(encoding.is_some() && encoding != Some(Encoding::Gzip));
+
+        headers.set(AcceptEncoding(vec![qitem(Encoding::Identity)]));
+        assert_eq!(negotiate(&headers), None);
+    }
+}
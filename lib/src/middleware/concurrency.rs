@@ -0,0 +1,366 @@
+//! Limits on the number of requests handled at once.
+//!
+//! [`ConcurrencyLimit`] caps the requests in flight across every worker thread, and
+//! optionally for the routes that match a pattern, so that a slow backend can't tie up the
+//! whole server. A request over a [`Limit`] waits in a bounded queue for one to finish, and is
+//! shed with `503 Service Unavailable` if the queue is full or it waits too long.
+//!
+//! ```rust,no_run
+//! # use std::time::Duration;
+//! # use shio::prelude::*;
+//! # use shio::middleware::{ConcurrencyLimit, Stack};
+//! # use shio::middleware::concurrency::Limit;
+//! # let router = shio::router::Router::new();
+//! let limit = ConcurrencyLimit::new(Limit::new(512).queue(1024, Duration::from_secs(5)))
+//!     .route("/reports/{id}", Limit::new(4));
+//!
+//! Shio::new(Stack::new(router).with(limit))
+//!     .run(":7878")
+//!     .unwrap();
+//! ```
+//!
+//! A request is counted until its handler responds; streaming the body of the response is
+//! not counted.
+//!
+//! [`ConcurrencyLimit`]: struct.ConcurrencyLimit.html
+//! [`Limit`]: struct.Limit.html
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use futures::{future, task, Async, Future, Poll};
+use futures::future::Either;
+use futures::task::Task;
+use hyper;
+use tokio_core::reactor::{Handle, Timeout};
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use response::Response;
+use router::Pattern;
+use super::{Middleware, Next};
+
+/// The number of requests that may be in flight at once, and how many more may wait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    max: usize,
+    queue: usize,
+    timeout: Duration,
+}
+
+impl Limit {
+    /// Allows `max` requests in flight, and sheds any more right away.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            queue: 0,
+            timeout: Duration::from_secs(0),
+        }
+    }
+
+    /// Lets up to `len` requests over the limit wait for up to `timeout` each.
+    pub fn queue(mut self, len: usize, timeout: Duration) -> Self {
+        self.queue = len;
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// [`Middleware`] that limits the number of requests in flight.
+///
+/// See the [module documentation](index.html) for details.
+///
+/// [`Middleware`]: ../trait.Middleware.html
+pub struct ConcurrencyLimit {
+    global: Arc<Semaphore>,
+    routes: Vec<(Pattern, Arc<Semaphore>)>,
+}
+
+impl ConcurrencyLimit {
+    /// Constructs a new `ConcurrencyLimit` that applies `limit` to all requests.
+    pub fn new(limit: Limit) -> Self {
+        Self {
+            global: Arc::new(Semaphore::new(limit)),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Also applies `limit` to the requests whose path matches `pattern`, such as
+    /// `/reports/{id}`.
+    ///
+    /// The first matching pattern is used. Such a request waits for the route's limit before
+    /// the global one.
+    pub fn route<P: Into<Pattern>>(mut self, pattern: P, limit: Limit) -> Self {
+        self.routes.push((pattern.into(), Arc::new(Semaphore::new(limit))));
+        self
+    }
+}
+
+impl fmt::Debug for ConcurrencyLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("global", &self.global.limit)
+            .field(
+                "routes",
+                &self.routes
+                    .iter()
+                    .map(|&(ref pattern, ref semaphore)| (pattern.source(), semaphore.limit))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Middleware for ConcurrencyLimit {
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let route = self.routes
+            .iter()
+            .find(|&&(ref pattern, _)| pattern.is_match(ctx.path()))
+            .map(|&(_, ref semaphore)| semaphore.clone());
+
+        let global = self.global.clone();
+        let handle = ctx.handle().clone();
+
+        let route = match route {
+            Some(route) => route.acquire(&handle),
+            None => future::ok(None).into_box(),
+        };
+
+        route
+            .and_then(move |route| {
+                global.acquire(&handle).map(move |global| (route, global))
+            })
+            .then(move |permits| -> BoxFuture<Response, hyper::Error> {
+                match permits {
+                    Ok((route, global)) => next.call(ctx)
+                        .then(move |result| {
+                            drop((route, global));
+                            result
+                        })
+                        .into_box(),
+
+                    Err(()) => {
+                        future::ok(Response::with(StatusCode::ServiceUnavailable)).into_box()
+                    }
+                }
+            })
+            .into_box()
+    }
+}
+
+/// Counts the requests in flight under a `Limit`, shared by every worker thread.
+struct Semaphore {
+    limit: Limit,
+    state: Mutex<State>,
+}
+
+struct State {
+    available: usize,
+    waiters: VecDeque<Arc<Waiter>>,
+}
+
+/// A request waiting in the queue.
+struct Waiter {
+    // Set once a finished request hands its permit to this one
+    granted: AtomicBool,
+    task: Mutex<Option<Task>>,
+}
+
+impl Semaphore {
+    fn new(limit: Limit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(State {
+                available: limit.max,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Returns a future that resolves to a permit, or fails if the request is shed.
+    fn acquire(self: &Arc<Self>, handle: &Handle) -> BoxFuture<Option<Permit>, ()> {
+        let waiter = {
+            let mut state = self.state.lock().unwrap();
+
+            if state.available > 0 && state.waiters.is_empty() {
+                state.available -= 1;
+
+                return future::ok(Some(Permit(self.clone()))).into_box();
+            }
+
+            if state.waiters.len() >= self.limit.queue {
+                return future::err(()).into_box();
+            }
+
+            let waiter = Arc::new(Waiter {
+                granted: AtomicBool::new(false),
+                task: Mutex::new(None),
+            });
+
+            state.waiters.push_back(waiter.clone());
+            waiter
+        };
+
+        let wait = Wait {
+            semaphore: self.clone(),
+            waiter,
+            done: false,
+        };
+
+        let timeout = match Timeout::new(self.limit.timeout, handle) {
+            Ok(timeout) => timeout,
+            Err(_) => return future::err(()).into_box(),
+        };
+
+        wait.select2(timeout)
+            .then(|result| match result {
+                Ok(Either::A((permit, _))) => Ok(Some(permit)),
+                _ => Err(()),
+            })
+            .into_box()
+    }
+
+    /// Hand a permit to the next waiter, or make it available.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        match state.waiters.pop_front() {
+            Some(waiter) => {
+                waiter.granted.store(true, Ordering::SeqCst);
+
+                if let Some(task) = waiter.task.lock().unwrap().take() {
+                    task.notify();
+                }
+            }
+
+            None => state.available += 1,
+        }
+    }
+}
+
+/// A request's place under a `Limit`, given back when dropped.
+struct Permit(Arc<Semaphore>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Waits in the queue for a permit.
+struct Wait {
+    semaphore: Arc<Semaphore>,
+    waiter: Arc<Waiter>,
+    done: bool,
+}
+
+impl Future for Wait {
+    type Item = Permit;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Permit, ()> {
+        // The task is stored before checking, so a permit granted in between still wakes it
+        *self.waiter.task.lock().unwrap() = Some(task::current());
+
+        if self.waiter.granted.load(Ordering::SeqCst) {
+            self.done = true;
+
+            return Ok(Async::Ready(Permit(self.semaphore.clone())));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let mut state = self.semaphore.state.lock().unwrap();
+
+        if self.waiter.granted.load(Ordering::SeqCst) {
+            // Granted after the last poll; pass the permit on
+            drop(state);
+            self.semaphore.release();
+        } else {
+            state.waiters.retain(|waiter| !Arc::ptr_eq(waiter, &self.waiter));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::sync::oneshot;
+    use futures::Future;
+    use tokio_core::reactor::Core;
+
+    use {Context, Response};
+    use http::StatusCode;
+    use test::TestClient;
+    use super::super::Stack;
+    use super::{ConcurrencyLimit, Limit, Semaphore};
+
+    #[test]
+    fn test_shed() {
+        let client = TestClient::new(
+            Stack::new(|_: Context| Response::new())
+                .with(ConcurrencyLimit::new(Limit::new(8)).route("/slow/{id}", Limit::new(0))),
+        );
+
+        client.get("/").send().assert_status(StatusCode::Ok);
+        client.get("/slow/1").send().assert_status(StatusCode::ServiceUnavailable);
+
+        // Permits are given back once the handler responds
+        for _ in 0..16 {
+            client.get("/").send().assert_status(StatusCode::Ok);
+        }
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let semaphore = Arc::new(Semaphore::new(Limit::new(1).queue(1, Duration::from_secs(5))));
+
+        let first = core.run(semaphore.acquire(&handle)).unwrap();
+        let queued = semaphore.acquire(&handle);
+
+        // The queue is full
+        assert!(core.run(semaphore.acquire(&handle)).is_err());
+
+        // The queued request gets the permit once the first finishes
+        let (finish, finished) = oneshot::channel::<()>();
+        handle.spawn(finished.then(move |_| {
+            drop(first);
+            Ok(())
+        }));
+
+        finish.send(()).unwrap();
+        assert!(core.run(queued).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let semaphore = Arc::new(Semaphore::new(Limit::new(1).queue(1, Duration::from_millis(10))));
+
+        let first = core.run(semaphore.acquire(&handle)).unwrap();
+        assert!(core.run(semaphore.acquire(&handle)).is_err());
+
+        // The request that timed out left the queue
+        assert!(semaphore.state.lock().unwrap().waiters.is_empty());
+
+        drop(first);
+        assert!(core.run(semaphore.acquire(&handle)).unwrap().is_some());
+    }
+}
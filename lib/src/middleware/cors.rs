@@ -0,0 +1,474 @@
+use std::cmp;
+use std::fmt;
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use hyper::{self, Method as HyperMethod};
+use unicase::Ascii;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::{Method, StatusCode};
+use http::header::{AccessControlAllowCredentials, AccessControlAllowHeaders,
+                   AccessControlAllowMethods, AccessControlAllowOrigin,
+                   AccessControlExposeHeaders, AccessControlMaxAge, AccessControlRequestHeaders,
+                   AccessControlRequestMethod, Headers};
+use response::Response;
+use super::{add_vary, Middleware, Next};
+
+/// An origin, or a set of origins, that may make cross-origin requests.
+#[derive(Clone)]
+enum AllowOrigin {
+    Any,
+    Exact(String),
+    // The text around a `*` that stands for a subdomain
+    Wildcard(String, String),
+    Predicate(Arc<Fn(&str) -> bool + Send + Sync>),
+}
+
+impl AllowOrigin {
+    fn matches(&self, origin: &str) -> bool {
+        match *self {
+            AllowOrigin::Any => true,
+            AllowOrigin::Exact(ref exact) => exact == origin,
+            AllowOrigin::Wildcard(ref prefix, ref suffix) => {
+                origin.len() > prefix.len() + suffix.len() && origin.starts_with(&**prefix)
+                    && origin.ends_with(&**suffix)
+                    && !origin[prefix.len()..origin.len() - suffix.len()].contains('/')
+            }
+
+            AllowOrigin::Predicate(ref predicate) => predicate(origin),
+        }
+    }
+}
+
+impl fmt::Debug for AllowOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AllowOrigin::Any => f.write_str("*"),
+            AllowOrigin::Exact(ref exact) => write!(f, "{:?}", exact),
+            AllowOrigin::Wildcard(ref prefix, ref suffix) => {
+                write!(f, "\"{}*{}\"", prefix, suffix)
+            }
+
+            AllowOrigin::Predicate(_) => f.write_str("<predicate>"),
+        }
+    }
+}
+
+/// [`Middleware`] that allows browsers to make cross-origin requests.
+///
+/// A preflight request, an `OPTIONS` request with an `Access-Control-Request-Method` header,
+/// is answered before it reaches the handler: with `204 No Content` and the allowed methods
+/// and headers if the origin, method, and headers are allowed, and with `403 Forbidden`
+/// otherwise. Any other request from an allowed origin is handled as usual, and the
+/// `Access-Control-Allow-Origin` and related headers are added to its response.
+///
+/// No origin is allowed until one is added.
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use shio::prelude::*;
+/// # use shio::middleware::{Cors, Stack};
+/// # let router = shio::router::Router::new();
+/// let cors = Cors::new()
+///     .allow_origin("https://app.example.com")
+///     .allow_origin("https://*.staging.example.com")
+///     .allow_methods(&[Method::GET, Method::POST, Method::DELETE])
+///     .allow_headers(&["Authorization", "Content-Type"])
+///     .allow_credentials(true)
+///     .max_age(Duration::from_secs(600));
+///
+/// Shio::new(Stack::new(router).with(cors))
+///     .run(":7878")
+///     .unwrap();
+/// ```
+///
+/// [`Middleware`]: trait.Middleware.html
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Vec<AllowOrigin>,
+    methods: Vec<HyperMethod>,
+    headers: Option<Vec<String>>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<u32>,
+}
+
+impl Cors {
+    /// Constructs a new `Cors` that allows no origins.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allows requests from `origin`.
+    ///
+    /// `origin` is either an exact origin such as `https://example.com`, an origin with a `*`
+    /// in place of a subdomain such as `https://*.example.com`, or `*` to allow any origin.
+    ///
+    /// # Panics
+    ///
+    /// If `origin` is `*` and credentials are allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        let origin = match origin.find('*') {
+            Some(_) if origin == "*" => {
+                assert!(!self.credentials, "credentials cannot be allowed from any origin");
+                AllowOrigin::Any
+            }
+
+            Some(index) => {
+                AllowOrigin::Wildcard(origin[..index].to_owned(), origin[index + 1..].to_owned())
+            }
+
+            None => AllowOrigin::Exact(origin.to_owned()),
+        };
+
+        self.origins.push(origin);
+        self
+    }
+
+    /// Allows requests from the origins for which `predicate` returns `true`.
+    pub fn allow_origin_fn<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origins.push(AllowOrigin::Predicate(Arc::new(predicate)));
+        self
+    }
+
+    /// Sets the methods of the requests allowed.
+    ///
+    /// Defaults to `GET`, `HEAD`, and `POST`.
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.iter().map(Method::to_hyper_method).collect();
+        self
+    }
+
+    /// Sets the request headers allowed.
+    ///
+    /// By default, any headers a preflight request asks for are allowed.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = Some(headers.iter().map(|&name| name.to_owned()).collect());
+        self
+    }
+
+    /// Sets the response headers, beyond the CORS-safelisted ones, that scripts may read.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.expose_headers = headers.iter().map(|&name| name.to_owned()).collect();
+        self
+    }
+
+    /// Sets whether requests may include credentials, such as cookies.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Panics
+    ///
+    /// If `credentials` is `true` and any origin is allowed with `*`, which would let every
+    /// website make requests with the user's credentials.
+    pub fn allow_credentials(mut self, credentials: bool) -> Self {
+        assert!(
+            !credentials || !self.allows_any_origin(),
+            "credentials cannot be allowed from any origin"
+        );
+
+        self.credentials = credentials;
+        self
+    }
+
+    /// Sets how long browsers may cache the answer to a preflight request.
+    ///
+    /// Durations longer than `u32::MAX` seconds are capped.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        let secs = cmp::min(max_age.as_secs(), u64::from(u32::MAX));
+        self.max_age = Some(secs as u32);
+        self
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    fn allows_any_origin(&self) -> bool {
+        self.origins.iter().any(|allowed| match *allowed {
+            AllowOrigin::Any => true,
+            _ => false,
+        })
+    }
+
+    /// Set the headers shared by the answers to preflight and actual requests.
+    ///
+    /// Returns whether the value of `Access-Control-Allow-Origin` depends on the origin.
+    fn set_origin(&self, headers: &mut Headers, origin: &str) -> bool {
+        if self.credentials {
+            headers.set(AccessControlAllowCredentials);
+        }
+
+        if self.allows_any_origin() {
+            headers.set(AccessControlAllowOrigin::Any);
+            false
+        } else {
+            headers.set(AccessControlAllowOrigin::Value(origin.to_owned()));
+            true
+        }
+    }
+
+    fn preflight(&self, headers: &Headers, origin: &str) -> Response {
+        let method = headers.get::<AccessControlRequestMethod>();
+        let requested = headers
+            .get::<AccessControlRequestHeaders>()
+            .map_or(&[][..], |requested| &requested.0[..]);
+
+        let allows_method = method.map_or(false, |method| self.methods.contains(&method.0));
+        let allows_headers = match self.headers {
+            Some(ref allowed) => requested.iter().all(|name| {
+                allowed
+                    .iter()
+                    .any(|allowed| name.eq_ignore_ascii_case(allowed))
+            }),
+
+            None => true,
+        };
+
+        let mut response = Response::new();
+
+        if !(self.allows_origin(origin) && allows_method && allows_headers) {
+            response.set_status(StatusCode::Forbidden);
+        } else {
+            let allowed_headers = match self.headers {
+                Some(ref allowed) => allowed.iter().map(|name| Ascii::new(name.clone())).collect(),
+                None => requested.to_vec(),
+            };
+
+            let headers = response.headers_mut();
+
+            self.set_origin(headers, origin);
+            headers.set(AccessControlAllowMethods(self.methods.clone()));
+
+            if !allowed_headers.is_empty() {
+                headers.set(AccessControlAllowHeaders(allowed_headers));
+            }
+
+            if let Some(max_age) = self.max_age {
+                headers.set(AccessControlMaxAge(max_age));
+            }
+
+            response.set_status(StatusCode::NoContent);
+        }
+
+        {
+            let headers = response.headers_mut();
+
+            add_vary(headers, "Origin");
+            add_vary(headers, "Access-Control-Request-Method");
+            add_vary(headers, "Access-Control-Request-Headers");
+        }
+
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            origins: Vec::new(),
+            methods: vec![HyperMethod::Get, HyperMethod::Head, HyperMethod::Post],
+            headers: None,
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let origin = ctx.headers()
+            .get_raw("Origin")
+            .and_then(|raw| raw.one())
+            .and_then(|line| str::from_utf8(line).ok())
+            .map(str::to_owned);
+
+        let origin = match origin {
+            Some(origin) => origin,
+
+            // Not a cross-origin request
+            None => return next.call(ctx),
+        };
+
+        let is_preflight = *ctx.method() == HyperMethod::Options
+            && ctx.headers().has::<AccessControlRequestMethod>();
+
+        if is_preflight {
+            return future::ok(self.preflight(ctx.headers(), &origin)).into_box();
+        }
+
+        if !self.allows_origin(&origin) {
+            // Handled as usual, but without the headers the browser would need to read it
+            return next.call(ctx)
+                .map(|mut response| {
+                    add_vary(response.headers_mut(), "Origin");
+                    response
+                })
+                .into_box();
+        }
+
+        let mut cors_headers = Headers::new();
+        let vary = self.set_origin(&mut cors_headers, &origin);
+
+        if !self.expose_headers.is_empty() {
+            cors_headers.set(AccessControlExposeHeaders(
+                self.expose_headers
+                    .iter()
+                    .map(|name| Ascii::new(name.clone()))
+                    .collect(),
+            ));
+        }
+
+        next.call(ctx)
+            .map(move |mut response| {
+                {
+                    let headers = response.headers_mut();
+                    headers.extend(cors_headers.iter());
+
+                    if vary {
+                        add_vary(headers, "Origin");
+                    }
+                }
+
+                response
+            })
+            .into_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::Method as HyperMethod;
+
+    use {Context, Response};
+    use http::{Method, StatusCode};
+    use http::header::{AccessControlAllowCredentials, AccessControlAllowMethods,
+                       AccessControlAllowOrigin, AccessControlExposeHeaders,
+                       AccessControlMaxAge, AccessControlRequestHeaders,
+                       AccessControlRequestMethod, Origin};
+    use test::{TestClient, TestResponse};
+    use super::super::Stack;
+    use super::Cors;
+
+    fn client(cors: Cors) -> TestClient<Stack> {
+        TestClient::new(Stack::new(|_: Context| Response::with("Hello")).with(cors))
+    }
+
+    #[test]
+    fn test_allow_origin() {
+        let cors = Cors::new()
+            .allow_origin("https://example.com")
+            .allow_origin("https://*.example.org")
+            .allow_origin_fn(|origin| origin.ends_with(".test"));
+
+        for &origin in &["https://example.com", "https://a.example.org", "http://local.test"] {
+            assert!(cors.allows_origin(origin), "{} is not allowed", origin);
+        }
+
+        for &origin in &[
+            "https://example.com.evil",
+            "http://example.com",
+            "https://.example.org",
+            "https://evil.com/.example.org",
+        ] {
+            assert!(!cors.allows_origin(origin), "{} is allowed", origin);
+        }
+    }
+
+    #[test]
+    fn test_preflight() {
+        let client = client(
+            Cors::new()
+                .allow_origin("https://example.com")
+                .allow_methods(&[Method::GET, Method::DELETE])
+                .allow_headers(&["Authorization"])
+                .max_age(Duration::from_secs(600)),
+        );
+
+        let preflight = |origin: &str, method: HyperMethod, headers: &[&str]| -> TestResponse {
+            client
+                .request(Method::OPTIONS, "/")
+                .header(Origin::new("https", origin.to_owned(), None))
+                .header(AccessControlRequestMethod(method))
+                .header(AccessControlRequestHeaders(
+                    headers.iter().map(|name| name.parse().unwrap()).collect(),
+                ))
+                .send()
+        };
+
+        let response = preflight("example.com", HyperMethod::Delete, &["authorization"]);
+        response
+            .assert_status(StatusCode::NoContent)
+            .assert_header(&AccessControlAllowOrigin::Value("https://example.com".into()))
+            .assert_header(&AccessControlAllowMethods(vec![
+                HyperMethod::Get,
+                HyperMethod::Delete,
+            ]))
+            .assert_header(&AccessControlMaxAge(600))
+            .assert_body("");
+
+        preflight("evil.com", HyperMethod::Delete, &[]).assert_status(StatusCode::Forbidden);
+        preflight("example.com", HyperMethod::Put, &[]).assert_status(StatusCode::Forbidden);
+        preflight("example.com", HyperMethod::Get, &["x-secret"])
+            .assert_status(StatusCode::Forbidden);
+    }
+
+    #[test]
+    fn test_actual_request() {
+        let client = client(Cors::new().allow_origin("*").expose_headers(&["ETag"]));
+
+        let response = client
+            .get("/")
+            .header(Origin::new("https", "example.com", None))
+            .send();
+
+        response
+            .assert_status(StatusCode::Ok)
+            .assert_header(&AccessControlAllowOrigin::Any)
+            .assert_header(&AccessControlExposeHeaders(vec!["ETag".parse().unwrap()]))
+            .assert_body("Hello");
+
+        // Requests that are not cross-origin are left alone
+        let response = client.get("/").send();
+        assert!(response.header::<AccessControlAllowOrigin>().is_none());
+
+        // Credentials require the origin to be echoed back
+        let client = TestClient::new(
+            Stack::new(|_: Context| Response::new()).with(
+                Cors::new()
+                    .allow_origin("https://example.com")
+                    .allow_credentials(true),
+            ),
+        );
+
+        client
+            .get("/")
+            .header(Origin::new("https", "example.com", None))
+            .send()
+            .assert_header(&AccessControlAllowOrigin::Value("https://example.com".into()))
+            .assert_header(&AccessControlAllowCredentials);
+    }
+
+    #[test]
+    fn test_max_age_is_capped() {
+        let cors = Cors::new().max_age(Duration::from_secs(u64::MAX));
+
+        assert_eq!(cors.max_age, Some(u32::MAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "credentials cannot be allowed from any origin")]
+    fn test_any_origin_with_credentials() {
+        Cors::new().allow_credentials(true).allow_origin("*");
+    }
+}
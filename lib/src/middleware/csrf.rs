@@ -0,0 +1,479 @@
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::str;
+
+use futures::{future, Async, Future, Stream};
+use hyper::{self, Method};
+
+use context::Context;
+use data::Data;
+use errors::Error;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{ContentType, Cookie, SetCookie};
+use response::Response;
+use router::Pattern;
+use state::Key;
+use util::{percent, random};
+use super::{Middleware, Next};
+
+// The number of random bytes in a token
+const TOKEN_BYTES: usize = 32;
+
+/// The CSRF token of a request, to be included in the forms and requests it leads to.
+///
+/// [`Csrf`] puts the token into the request state, where templates may read it with
+/// `ctx.get::<CsrfToken>()`.
+///
+/// [`Csrf`]: struct.Csrf.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    fn generate() -> Self {
+        let mut bytes = [0; TOKEN_BYTES];
+        random::fill_secret(&mut bytes);
+
+        CsrfToken(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    fn parse(token: &str) -> Option<Self> {
+        let is_valid =
+            token.len() == TOKEN_BYTES * 2 && token.bytes().all(|b| b.is_ascii_hexdigit());
+
+        if is_valid {
+            Some(CsrfToken(token.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the token as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `submitted` is this token, compared in constant time.
+    fn verify(&self, submitted: &str) -> bool {
+        let (a, b) = (self.0.as_bytes(), submitted.as_bytes());
+
+        a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+}
+
+impl Deref for CsrfToken {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Key for CsrfToken {
+    type Value = Self;
+}
+
+/// [`Middleware`] that protects against cross-site request forgery with a double-submit
+/// cookie.
+///
+/// Every request is given a [`CsrfToken`], which is kept in a cookie (`csrf_token` by
+/// default) and put into the request state. A request with an unsafe method, such as `POST`,
+/// must submit the same token in the `X-CSRF-Token` header or, for a form encoded as
+/// `application/x-www-form-urlencoded`, in the `csrf_token` field; it is answered with
+/// `403 Forbidden` otherwise. A site on another origin can't read the cookie, so it can't
+/// submit the token.
+///
+/// ```rust,no_run
+/// # use shio::prelude::*;
+/// # use shio::middleware::{Csrf, CsrfToken, Stack};
+/// fn form(ctx: Context) -> Response {
+///     Response::with(format!(
+///         "<form method=\"post\"><input type=\"hidden\" name=\"csrf_token\" value=\"{}\">\
+///          <button>Send</button></form>",
+///         ctx.get::<CsrfToken>()
+///     ))
+/// }
+///
+/// let mut router = shio::router::Router::new();
+/// router.add((Method::GET, "/", form));
+///
+/// Shio::new(Stack::new(router).with(Csrf::new().exempt("/webhooks/{provider}")))
+///     .run(":7878")
+///     .unwrap();
+/// ```
+///
+/// [`Middleware`]: trait.Middleware.html
+/// [`CsrfToken`]: struct.CsrfToken.html
+pub struct Csrf {
+    cookie_name: String,
+    header_name: String,
+    field_name: String,
+    secure: bool,
+    limit: usize,
+    exempt: Vec<Pattern>,
+}
+
+impl Csrf {
+    /// Constructs a new `Csrf`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the name of the cookie that keeps the token.
+    ///
+    /// Defaults to `csrf_token`.
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    /// Sets the name of the header a token may be submitted in.
+    ///
+    /// Defaults to `X-CSRF-Token`.
+    pub fn header_name(mut self, name: &str) -> Self {
+        self.header_name = name.to_owned();
+        self
+    }
+
+    /// Sets the name of the form field a token may be submitted in.
+    ///
+    /// Defaults to `csrf_token`.
+    pub fn field_name(mut self, name: &str) -> Self {
+        self.field_name = name.to_owned();
+        self
+    }
+
+    /// Sets whether the cookie is only sent over HTTPS.
+    ///
+    /// Defaults to `true`.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets the size, in bytes, of the largest form that is read to find the token.
+    ///
+    /// Larger forms are answered with `413 Payload Too Large`. Defaults to 1 MiB.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Exempts requests whose path matches `pattern`, such as `/webhooks/{provider}`, from
+    /// verification.
+    ///
+    /// Useful for endpoints that authenticate requests by other means.
+    pub fn exempt<P: Into<Pattern>>(mut self, pattern: P) -> Self {
+        self.exempt.push(pattern.into());
+        self
+    }
+
+    fn cookie(&self, token: &CsrfToken) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax",
+            self.cookie_name, token
+        );
+
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+
+        cookie
+    }
+}
+
+impl Default for Csrf {
+    fn default() -> Self {
+        Self {
+            cookie_name: "csrf_token".into(),
+            header_name: "X-CSRF-Token".into(),
+            field_name: "csrf_token".into(),
+            secure: true,
+            limit: 1024 * 1024,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Csrf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Csrf")
+            .field("cookie_name", &self.cookie_name)
+            .field("header_name", &self.header_name)
+            .field("field_name", &self.field_name)
+            .field("secure", &self.secure)
+            .field("limit", &self.limit)
+            .field(
+                "exempt",
+                &self.exempt.iter().map(Pattern::source).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Middleware for Csrf {
+    fn call(&self, mut ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let cookie = ctx.headers()
+            .get::<Cookie>()
+            .and_then(|cookie| cookie.get(&self.cookie_name))
+            .and_then(CsrfToken::parse);
+
+        let is_safe = match *ctx.method() {
+            Method::Get | Method::Head | Method::Options | Method::Trace => true,
+            _ => false,
+        };
+
+        let is_exempt = self.exempt.iter().any(|pattern| pattern.is_match(ctx.path()));
+
+        if is_safe || is_exempt {
+            let (token, set_cookie) = match cookie {
+                Some(token) => (token, None),
+                None => {
+                    let token = CsrfToken::generate();
+                    let set_cookie = self.cookie(&token);
+
+                    (token, Some(set_cookie))
+                }
+            };
+
+            ctx.put::<CsrfToken>(token);
+
+            return next.call(ctx)
+                .map(move |mut response| {
+                    if let Some(set_cookie) = set_cookie {
+                        let headers = response.headers_mut();
+                        let mut cookies = headers
+                            .get::<SetCookie>()
+                            .map_or_else(Vec::new, |cookies| cookies.0.clone());
+
+                        cookies.push(set_cookie);
+                        headers.set(SetCookie(cookies));
+                    }
+
+                    response
+                })
+                .into_box();
+        }
+
+        let token = match cookie {
+            Some(token) => token,
+            None => return future::ok(Response::with(StatusCode::Forbidden)).into_box(),
+        };
+
+        let submitted = ctx.headers()
+            .get_raw(&self.header_name)
+            .and_then(|raw| raw.one())
+            .and_then(|line| str::from_utf8(line).ok())
+            .map(str::to_owned);
+
+        if let Some(submitted) = submitted {
+            if !token.verify(submitted.trim()) {
+                return future::ok(Response::with(StatusCode::Forbidden)).into_box();
+            }
+
+            ctx.put::<CsrfToken>(token);
+
+            return next.call(ctx);
+        }
+
+        let is_form = ctx.headers().get::<ContentType>().map_or(false, |content_type| {
+            content_type.type_() == "application"
+                && content_type.subtype() == "x-www-form-urlencoded"
+        });
+
+        if !is_form {
+            return future::ok(Response::with(StatusCode::Forbidden)).into_box();
+        }
+
+        // The form is read to find the token, and given to the handler once it is verified
+        let (handle, mut state, request, data) = ctx.deconstruct();
+        let field = self.field_name.clone();
+
+        read_form(data, self.limit)
+            .then(move |result| match result {
+                Ok(Some(body)) => {
+                    let is_valid =
+                        form_field(&body, &field).map_or(false, |value| token.verify(&value));
+
+                    if !is_valid {
+                        return future::ok(Response::with(StatusCode::Forbidden)).into_box();
+                    }
+
+                    state.put::<CsrfToken>(token);
+
+                    next.call(Context::new(handle, request, state, body.into()))
+                }
+
+                Ok(None) => future::ok(Response::with(StatusCode::PayloadTooLarge)).into_box(),
+                Err(_) => future::ok(Response::with(StatusCode::BadRequest)).into_box(),
+            })
+            .into_box()
+    }
+}
+
+/// Read a form of at most `limit` bytes, or `None` if it is larger.
+fn read_form(mut data: Data, limit: usize) -> BoxFuture<Option<Vec<u8>>, Error> {
+    let mut body = Vec::new();
+
+    future::poll_fn(move || loop {
+        match try_ready!(data.poll()) {
+            Some(chunk) => {
+                if body.len() + chunk.len() > limit {
+                    return Ok(Async::Ready(None));
+                }
+
+                body.extend_from_slice(&chunk);
+            }
+
+            None => return Ok(Async::Ready(Some(mem::replace(&mut body, Vec::new())))),
+        }
+    }).into_box()
+}
+
+/// The value of the field `name` in a form encoded as `application/x-www-form-urlencoded`.
+fn form_field(form: &[u8], name: &str) -> Option<String> {
+    let form = str::from_utf8(form).ok()?;
+
+    form.split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = percent::decode_form(parts.next()?)?;
+            let value = percent::decode_form(parts.next().unwrap_or(""))?;
+
+            Some((key, value))
+        })
+        .find(|&(ref key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use futures::{Future, Stream};
+    use hyper;
+    use hyper::header::parsing;
+
+    use {Context, Response};
+    use ext::BoxFuture;
+    use http::{Method, StatusCode};
+    use http::header::{ContentType, Cookie, Formatter, Header, Raw, SetCookie};
+    use test::TestClient;
+    use super::super::Stack;
+    use super::{form_field, Csrf, CsrfToken};
+
+    fn echo(ctx: Context) -> BoxFuture<Response, ::errors::Error> {
+        let token = ctx.get::<CsrfToken>().to_string();
+
+        Box::new(ctx.data().concat2().map(move |body| {
+            Response::with(format!("{} {}", token, String::from_utf8_lossy(&body)))
+        }))
+    }
+
+    fn client() -> TestClient<Stack> {
+        TestClient::new(Stack::new(echo).with(Csrf::new().exempt("/webhooks/{provider}")))
+    }
+
+    #[derive(Clone)]
+    struct Submitted(String);
+
+    impl Header for Submitted {
+        fn header_name() -> &'static str {
+            "X-CSRF-Token"
+        }
+
+        fn parse_header(raw: &Raw) -> hyper::Result<Self> {
+            parsing::from_one_raw_str(raw).map(Submitted)
+        }
+
+        fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+            f.fmt_line(&self.0)
+        }
+    }
+
+    fn cookie(token: &str) -> Cookie {
+        let mut cookie = Cookie::new();
+        cookie.append("csrf_token", token.to_owned());
+        cookie
+    }
+
+    #[test]
+    fn test_issue_token() {
+        let client = client();
+        let response = client.get("/").send();
+
+        let set_cookie = &response.header::<SetCookie>().unwrap().0[0];
+        let token = response.text().trim();
+
+        assert_eq!(token.len(), 64);
+        assert_eq!(
+            *set_cookie,
+            format!("csrf_token={}; Path=/; HttpOnly; SameSite=Lax; Secure", token)
+        );
+
+        // An existing token is kept
+        let response = client.get("/").header(cookie(token)).send();
+        assert!(response.header::<SetCookie>().is_none());
+        assert_eq!(response.text().trim(), token);
+    }
+
+    #[test]
+    fn test_verify() {
+        let client = client();
+        let token = client.get("/").send().text().trim().to_owned();
+
+        client.post("/").send().assert_status(StatusCode::Forbidden);
+        client
+            .post("/")
+            .header(cookie(&token))
+            .send()
+            .assert_status(StatusCode::Forbidden);
+
+        client
+            .post("/")
+            .header(cookie(&token))
+            .header(Submitted(token.clone()))
+            .send()
+            .assert_status(StatusCode::Ok);
+
+        client
+            .post("/")
+            .header(cookie(&token))
+            .header(Submitted("0".repeat(64)))
+            .send()
+            .assert_status(StatusCode::Forbidden);
+
+        client
+            .request(Method::POST, "/")
+            .header(cookie(&token))
+            .header(ContentType::form_url_encoded())
+            .body(format!("name=a+b&csrf_token={}", token))
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_body(&format!("{} name=a+b&csrf_token={}", token, token));
+
+        client
+            .post("/")
+            .header(cookie(&token))
+            .header(ContentType::form_url_encoded())
+            .body("csrf_token=forged")
+            .send()
+            .assert_status(StatusCode::Forbidden);
+
+        client.post("/webhooks/github").send().assert_status(StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_form_field() {
+        assert_eq!(form_field(b"a=1&b=x+y%21", "b"), Some("x y!".to_owned()));
+        assert_eq!(form_field(b"a=1&b", "b"), Some(String::new()));
+        assert_eq!(form_field(b"a=1", "b"), None);
+    }
+}
@@ -0,0 +1,324 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use flate2::write::{GzDecoder, ZlibDecoder};
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::{self, Chunk};
+
+use context::Context;
+use data::Data;
+use errors::Error;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{qitem, AcceptEncoding, ContentEncoding, ContentLength, Encoding};
+use response::Response;
+use super::{Middleware, Next};
+
+// Compressed input is decoded this many bytes at a time, so a small chunk that inflates to
+// a huge amount of data is caught soon after the limit is crossed
+const INPUT_SIZE: usize = 1024;
+
+/// [`Middleware`] that decompresses request bodies sent with a `Content-Encoding` of `gzip`
+/// or `deflate`.
+///
+/// The [`Data`] seen by the handler is the decompressed body, and the `Content-Encoding` and
+/// `Content-Length` headers of the request are removed. Requests in any other encoding are
+/// answered with `415 Unsupported Media Type`.
+///
+/// Reading more than `Decompress::limit` bytes of decompressed data fails. If the handler
+/// then fails with that error, the response is `413 Payload Too Large`; likewise a body that
+/// is not valid compressed data leads to `400 Bad Request`.
+///
+/// [`Middleware`]: trait.Middleware.html
+/// [`Data`]: ../struct.Data.html
+#[derive(Clone, Debug)]
+pub struct Decompress {
+    limit: u64,
+}
+
+impl Decompress {
+    /// Constructs a new `Decompress`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the most bytes a request body may decompress to.
+    ///
+    /// Defaults to 10 MiB.
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+impl Default for Decompress {
+    fn default() -> Self {
+        Self {
+            limit: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl Middleware for Decompress {
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let decoder = match ctx.headers().get::<ContentEncoding>() {
+            None => return next.call(ctx),
+            Some(&ContentEncoding(ref encodings)) => match encodings.as_slice() {
+                [] | [Encoding::Identity] => return next.call(ctx),
+                [Encoding::Gzip] => Decoder::Gzip(GzDecoder::new(Vec::new())),
+                [Encoding::Deflate] => Decoder::Deflate(ZlibDecoder::new(Vec::new())),
+                _ => return future::ok(unsupported()).into_box(),
+            },
+        };
+
+        let (handle, state, mut request, data) = ctx.deconstruct();
+        request.headers_mut().remove::<ContentEncoding>();
+        request.headers_mut().remove::<ContentLength>();
+
+        let failure = Rc::new(Cell::new(None));
+        let data = Data::from_stream(DecompressStream {
+            data,
+            decoder: Some(decoder),
+            limit: self.limit,
+            emitted: 0,
+            failure: failure.clone(),
+        });
+
+        next.call(Context::new(handle, request, state, data))
+            .map(move |response| match failure.get() {
+                // The handler gave up on the body and answered with a generic error
+                Some(status) if response.status() == StatusCode::InternalServerError => {
+                    Response::with(status)
+                }
+
+                _ => response,
+            })
+            .into_box()
+    }
+}
+
+/// Responds to a request with an encoding that can't be decompressed.
+fn unsupported() -> Response {
+    Response::build()
+        .status(StatusCode::UnsupportedMediaType)
+        .header(AcceptEncoding(vec![qitem(Encoding::Gzip), qitem(Encoding::Deflate)]))
+        .into()
+}
+
+enum Decoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl Decoder {
+    /// The decompressed output written so far.
+    fn output(&mut self) -> &mut Vec<u8> {
+        match *self {
+            Decoder::Gzip(ref mut decoder) => decoder.get_mut(),
+            Decoder::Deflate(ref mut decoder) => decoder.get_mut(),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.finish(),
+            Decoder::Deflate(decoder) => decoder.finish(),
+        }
+    }
+}
+
+impl Write for Decoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Decoder::Gzip(ref mut decoder) => decoder.write(buf),
+            Decoder::Deflate(ref mut decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Decoder::Gzip(ref mut decoder) => decoder.flush(),
+            Decoder::Deflate(ref mut decoder) => decoder.flush(),
+        }
+    }
+}
+
+/// Decompresses the chunks of `data` as they arrive.
+struct DecompressStream {
+    data: Data,
+    // `None` once the decoder has been finished
+    decoder: Option<Decoder>,
+    limit: u64,
+    // Total bytes of decompressed output returned so far
+    emitted: u64,
+    // The status to respond with if the handler fails, once decompression has failed
+    failure: Rc<Cell<Option<StatusCode>>>,
+}
+
+impl DecompressStream {
+    fn fail(&mut self, status: StatusCode, message: &str) -> Error {
+        self.decoder = None;
+        self.failure.set(Some(status));
+
+        hyper::Error::Io(io::Error::new(io::ErrorKind::InvalidData, message)).into()
+    }
+
+    /// Fail if `len` more bytes of output would exceed the limit.
+    fn check_limit(&mut self, len: usize) -> Result<(), Error> {
+        if self.emitted + len as u64 > self.limit {
+            return Err(self.fail(
+                StatusCode::PayloadTooLarge,
+                "decompressed request body is too large",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn emit(&mut self, output: Vec<u8>) -> Poll<Option<Chunk>, Error> {
+        self.check_limit(output.len())?;
+        self.emitted += output.len() as u64;
+
+        Ok(Async::Ready(Some(output.into())))
+    }
+}
+
+impl Stream for DecompressStream {
+    type Item = Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        loop {
+            if self.decoder.is_none() {
+                return Ok(Async::Ready(None));
+            }
+
+            match try_ready!(self.data.poll()) {
+                Some(chunk) => {
+                    let mut decoder = self.decoder.take().unwrap();
+
+                    for input in chunk.chunks(INPUT_SIZE) {
+                        if decoder.write_all(input).is_err() {
+                            let message = "invalid compressed data";
+                            return Err(self.fail(StatusCode::BadRequest, message));
+                        }
+
+                        self.check_limit(decoder.output().len())?;
+                    }
+
+                    let output = mem::replace(decoder.output(), Vec::new());
+                    self.decoder = Some(decoder);
+
+                    if !output.is_empty() {
+                        return self.emit(output);
+                    }
+                }
+
+                None => {
+                    let decoder = self.decoder.take().unwrap();
+                    let output = match decoder.finish() {
+                        Ok(output) => output,
+                        Err(_) => {
+                            let message = "truncated compressed data";
+                            return Err(self.fail(StatusCode::BadRequest, message));
+                        }
+                    };
+
+                    if output.is_empty() {
+                        return Ok(Async::Ready(None));
+                    }
+
+                    return self.emit(output);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use futures::{Future, Stream};
+
+    use {Context, Response};
+    use http::StatusCode;
+    use http::header::{ContentEncoding, Encoding};
+    use test::TestClient;
+    use super::super::Stack;
+    use super::Decompress;
+
+    fn echo(ctx: Context) -> Box<Future<Item = Response, Error = ::Error>> {
+        Box::new(
+            ctx.data()
+                .concat2()
+                .map(|body| Response::with(String::from_utf8_lossy(&body).into_owned())),
+        )
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decompress() {
+        let client = TestClient::new(Stack::new(echo).with(Decompress::new()));
+
+        client
+            .post("/")
+            .header(ContentEncoding(vec![Encoding::Gzip]))
+            .body(gzip(b"Hello World"))
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_body("Hello World");
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"Hello World").unwrap();
+
+        client
+            .post("/")
+            .header(ContentEncoding(vec![Encoding::Deflate]))
+            .body(encoder.finish().unwrap())
+            .send()
+            .assert_body("Hello World");
+
+        client.post("/").body("Hello World").send().assert_body("Hello World");
+    }
+
+    #[test]
+    fn test_limit() {
+        let client = TestClient::new(Stack::new(echo).with(Decompress::new().limit(1024)));
+
+        client
+            .post("/")
+            .header(ContentEncoding(vec![Encoding::Gzip]))
+            .body(gzip(&[0; 64 * 1024]))
+            .send()
+            .assert_status(StatusCode::PayloadTooLarge);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let client = TestClient::new(Stack::new(echo).with(Decompress::new()));
+
+        client
+            .post("/")
+            .header(ContentEncoding(vec![Encoding::Gzip]))
+            .body("Hello World")
+            .send()
+            .assert_status(StatusCode::BadRequest);
+
+        client
+            .post("/")
+            .header(ContentEncoding(vec![Encoding::Brotli]))
+            .body("Hello World")
+            .send()
+            .assert_status(StatusCode::UnsupportedMediaType);
+    }
+}
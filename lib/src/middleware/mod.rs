@@ -0,0 +1,200 @@
+//! Middleware that runs around a [`Handler`].
+//!
+//! A [`Middleware`] receives each request's [`Context`] along with [`Next`], the rest of the
+//! chain. It may answer the request itself, or pass the context on and inspect or change the
+//! response on its way back out.
+//!
+//! Middleware is assembled around a root handler with a [`Stack`]:
+//!
+//! ```rust,no_run
+//! # use shio::prelude::*;
+//! # use shio::middleware::{Compress, Stack};
+//! let mut router = shio::router::Router::new();
+//! router.add((Method::GET, "/", |_| Response::with("Hello World\n")));
+//!
+//! Shio::new(Stack::new(router).with(Compress::new()))
+//!     .run(":7878")
+//!     .unwrap();
+//! ```
+//!
+//! [`Handler`]: ../trait.Handler.html
+//! [`Middleware`]: trait.Middleware.html
+//! [`Context`]: ../context/struct.Context.html
+//! [`Next`]: struct.Next.html
+//! [`Stack`]: struct.Stack.html
+
+pub mod access_log;
+mod compress;
+pub mod concurrency;
+mod cors;
+mod csrf;
+mod decompress;
+pub mod rate_limit;
+mod request_id;
+mod security_headers;
+
+pub use self::access_log::AccessLog;
+pub use self::compress::Compress;
+pub use self::concurrency::ConcurrencyLimit;
+pub use self::cors::Cors;
+pub use self::csrf::{Csrf, CsrfToken};
+pub use self::decompress::Decompress;
+pub use self::rate_limit::RateLimit;
+pub use self::request_id::{RequestId, RequestIds};
+pub use self::security_headers::{CspNonce, SecurityHeaders};
+
+use std::sync::Arc;
+
+use hyper;
+
+use context::Context;
+use ext::BoxFuture;
+use handler::{BoxHandler, Handler};
+use http::header::{Headers, Vary};
+use response::Response;
+
+/// Code that runs around the handling of every request.
+pub trait Middleware: Send + Sync {
+    /// Handle a request, usually by calling `next` with the context.
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Context, Next) -> BoxFuture<Response, hyper::Error> + Send + Sync,
+{
+    #[inline]
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        (*self)(ctx, next)
+    }
+}
+
+/// The remainder of a [`Stack`]: any inner middleware followed by the root handler.
+///
+/// [`Stack`]: struct.Stack.html
+#[derive(Clone)]
+pub struct Next {
+    handler: Arc<BoxHandler>,
+    middleware: Arc<Vec<Box<Middleware>>>,
+    index: usize,
+}
+
+impl Next {
+    /// Pass the request on to the rest of the stack.
+    pub fn call(self, ctx: Context) -> BoxFuture<Response, hyper::Error> {
+        match self.middleware.get(self.index) {
+            Some(middleware) => middleware.call(
+                ctx,
+                Next {
+                    handler: self.handler.clone(),
+                    middleware: self.middleware.clone(),
+                    index: self.index + 1,
+                },
+            ),
+
+            None => self.handler.call(ctx),
+        }
+    }
+}
+
+/// A [`Handler`] that runs a root handler inside layers of [`Middleware`].
+///
+/// Middleware runs in the order it is added; the first middleware added sees the request
+/// first and the response last.
+///
+/// [`Handler`]: ../trait.Handler.html
+/// [`Middleware`]: trait.Middleware.html
+pub struct Stack {
+    handler: Arc<BoxHandler>,
+    middleware: Arc<Vec<Box<Middleware>>>,
+}
+
+impl Stack {
+    /// Constructs a new `Stack` around the root `handler`.
+    pub fn new<H: Handler + 'static>(handler: H) -> Self
+    where
+        <H::Result as ::futures::IntoFuture>::Error: ::std::fmt::Debug + Send,
+    {
+        Self {
+            handler: Arc::new(handler.into_box()),
+            middleware: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Add `middleware` inside any middleware already in the stack.
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        Arc::get_mut(&mut self.middleware)
+            .expect("a stack is not shared until it handles requests")
+            .push(Box::new(middleware));
+
+        self
+    }
+}
+
+impl Handler for Stack {
+    type Result = BoxFuture<Response, hyper::Error>;
+
+    #[inline]
+    fn call(&self, ctx: Context) -> Self::Result {
+        Next {
+            handler: self.handler.clone(),
+            middleware: self.middleware.clone(),
+            index: 0,
+        }.call(ctx)
+    }
+}
+
+/// Add `name` to `Vary`, as the response may differ by that request header.
+pub(crate) fn add_vary(headers: &mut Headers, name: &str) {
+    let mut items = match headers.get::<Vary>() {
+        Some(&Vary::Any) => return,
+        Some(&Vary::Items(ref items)) => items.clone(),
+        None => Vec::new(),
+    };
+
+    if !items.iter().any(|item| item.eq_ignore_ascii_case(name)) {
+        items.push(name.parse().unwrap());
+    }
+
+    headers.set(Vary::Items(items));
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+
+    use {Context, Response};
+    use ext::{BoxFuture, FutureExt};
+    use http::header::Server;
+    use test::TestClient;
+    use super::{Next, Stack};
+
+    fn tag(name: &'static str) -> impl Fn(Context, Next) -> BoxFuture<Response, ::hyper::Error> {
+        move |ctx, next: Next| {
+            next.call(ctx)
+                .map(move |mut response| {
+                    let server = match response.headers().get::<Server>() {
+                        Some(server) => format!("{}, {}", server, name),
+                        None => name.to_owned(),
+                    };
+
+                    response.headers_mut().set(Server::new(server));
+                    response
+                })
+                .into_box()
+        }
+    }
+
+    #[test]
+    fn test_stack_order() {
+        let stack = Stack::new(|_: Context| Response::with("Hello"))
+            .with(tag("outer"))
+            .with(tag("inner"));
+
+        TestClient::new(stack)
+            .get("/")
+            .send()
+            .assert_header(&Server::new("inner, outer"))
+            .assert_body("Hello");
+    }
+}
@@ -0,0 +1,559 @@
+//! Rate limiting of requests with the generic cell rate algorithm (GCRA).
+//!
+//! [`RateLimit`] allows each client a [`Quota`] of requests per period, with bursts of up to
+//! the whole quota. Clients are told of their limit in the `RateLimit-Limit`,
+//! `RateLimit-Remaining`, and `RateLimit-Reset` headers of every response, and a request over
+//! the limit is answered with `429 Too Many Requests` and a `Retry-After` header.
+//!
+//! Clients are told apart by their IP address by default, or by any key taken from the
+//! [`Context`]. The state of every client is kept in a [`Store`], shared by every worker
+//! thread; [`MemoryStore`] keeps it in memory, and other stores may share it between
+//! servers.
+//!
+//! ```rust,no_run
+//! # use shio::prelude::*;
+//! # use shio::middleware::{RateLimit, Stack};
+//! # use shio::middleware::rate_limit::Quota;
+//! # let router = shio::router::Router::new();
+//! let limit = RateLimit::new(Quota::per_minute(120))
+//!     .route("/login", Quota::per_minute(5));
+//!
+//! Shio::new(Stack::new(router).with(limit))
+//!     .run(":7878")
+//!     .unwrap();
+//! ```
+//!
+//! [`RateLimit`]: struct.RateLimit.html
+//! [`Quota`]: struct.Quota.html
+//! [`Context`]: ../../context/struct.Context.html
+//! [`Store`]: trait.Store.html
+//! [`MemoryStore`]: struct.MemoryStore.html
+
+use std::cmp;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{future, Future};
+use hyper;
+
+use context::{Context, Key};
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{Headers, RetryAfter};
+use response::Response;
+use router::Pattern;
+use super::{Middleware, Next};
+
+// The number of keys a `MemoryStore` holds before it first removes expired ones
+const SWEEP_THRESHOLD: usize = 1024;
+
+/// The number of requests a client may make in a period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    limit: u32,
+    period: Duration,
+}
+
+impl Quota {
+    /// Allows `limit` requests every `period`.
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is zero, or so large that `period` can't be split evenly into that many
+    /// non-zero intervals.
+    pub fn new(limit: u32, period: Duration) -> Self {
+        assert!(limit > 0, "a quota must allow at least one request");
+        assert!(
+            period / limit > Duration::from_secs(0),
+            "a quota must allow at most one request per nanosecond"
+        );
+
+        Self { limit, period }
+    }
+
+    /// Allows `limit` requests every second.
+    pub fn per_second(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    /// Allows `limit` requests every minute.
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    /// Allows `limit` requests every hour.
+    pub fn per_hour(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60 * 60))
+    }
+
+    /// Decide whether a request made at `now` is allowed, given the theoretical arrival time
+    /// `tat` stored for its client.
+    ///
+    /// Returns the decision, and the theoretical arrival time to store. Both times are
+    /// measured since the Unix epoch.
+    pub fn check(&self, tat: Option<Duration>, now: Duration) -> (Decision, Duration) {
+        let interval = self.period / self.limit;
+        let tat = cmp::max(tat.unwrap_or(now), now);
+        let next = tat + interval;
+
+        // A request is allowed if it would not take the client more than a period ahead
+        if next > now + self.period {
+            let decision = Decision {
+                limit: self.limit,
+                remaining: 0,
+                reset: tat - now,
+                retry_after: Some(next - self.period - now),
+            };
+
+            return (decision, tat);
+        }
+
+        let headroom = now + self.period - next;
+        let decision = Decision {
+            limit: self.limit,
+            remaining: (nanos(headroom) / nanos(interval)) as u32,
+            reset: next - now,
+            retry_after: None,
+        };
+
+        (decision, next)
+    }
+}
+
+/// Whether a request is allowed, and the state of its client's quota.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    /// The number of requests allowed in a period.
+    pub limit: u32,
+
+    /// The number of requests the client may still make right away.
+    pub remaining: u32,
+
+    /// How long until the whole quota is available again.
+    pub reset: Duration,
+
+    /// How long until a request would be allowed, if this one was not.
+    pub retry_after: Option<Duration>,
+}
+
+impl Decision {
+    /// Whether the request is allowed.
+    pub fn is_allowed(&self) -> bool {
+        self.retry_after.is_none()
+    }
+}
+
+/// The state of every client's quota.
+///
+/// A store must apply `Quota::check` atomically for each key, as it may be called for the
+/// same key from many threads, or many servers, at once.
+pub trait Store: Send + Sync {
+    /// Decide whether the client `key` may make a request at `now`, measured since the
+    /// Unix epoch, and record it if so.
+    fn acquire(
+        &self,
+        key: &str,
+        quota: &Quota,
+        now: Duration,
+    ) -> BoxFuture<Decision, Box<StdError + Send + Sync>>;
+}
+
+/// A [`Store`] that keeps quotas in the memory of this process.
+///
+/// Clones share the same quotas.
+///
+/// [`Store`]: trait.Store.html
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<MemoryInner>>,
+}
+
+#[derive(Default)]
+struct MemoryInner {
+    tats: HashMap<String, Duration>,
+    next_sweep: usize,
+}
+
+impl MemoryStore {
+    /// Constructs a new, empty `MemoryStore`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("keys", &self.inner.lock().unwrap().tats.len())
+            .finish()
+    }
+}
+
+impl Store for MemoryStore {
+    fn acquire(
+        &self,
+        key: &str,
+        quota: &Quota,
+        now: Duration,
+    ) -> BoxFuture<Decision, Box<StdError + Send + Sync>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        // Keys are forgotten once their quota is whole again
+        if inner.tats.len() >= cmp::max(inner.next_sweep, SWEEP_THRESHOLD) {
+            inner.tats.retain(|_, tat| *tat > now);
+            inner.next_sweep = inner.tats.len() * 2;
+        }
+
+        let (decision, tat) = quota.check(inner.tats.get(key).cloned(), now);
+        inner.tats.insert(key.to_owned(), tat);
+
+        future::ok(decision).into_box()
+    }
+}
+
+type KeyFn = Box<Fn(&Context) -> Option<String> + Send + Sync>;
+
+/// [`Middleware`] that limits the rate of requests from each client.
+///
+/// See the [module documentation](index.html) for details.
+///
+/// [`Middleware`]: ../trait.Middleware.html
+pub struct RateLimit {
+    quota: Quota,
+    routes: Vec<(Pattern, Quota)>,
+    key: KeyFn,
+    store: Arc<Store>,
+}
+
+impl RateLimit {
+    /// Constructs a new `RateLimit` that allows each client `quota`, told apart by their IP
+    /// address and kept in a new [`MemoryStore`].
+    ///
+    /// [`MemoryStore`]: struct.MemoryStore.html
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            routes: Vec::new(),
+            key: Box::new(|ctx: &Context| ctx.remote_addr().map(|addr| addr.ip().to_string())),
+            store: Arc::new(MemoryStore::new()),
+        }
+    }
+
+    /// Allows each client `quota` for requests whose path matches `pattern`, such as
+    /// `/login`, instead of the default.
+    ///
+    /// Requests to each pattern count against a separate quota. The first matching pattern
+    /// is used.
+    pub fn route<P: Into<Pattern>>(mut self, pattern: P, quota: Quota) -> Self {
+        self.routes.push((pattern.into(), quota));
+        self
+    }
+
+    /// Tells clients apart by the value of `K` in the request state, such as the user put
+    /// there by authentication middleware.
+    ///
+    /// Requests without a `K` are told apart by their IP address.
+    pub fn by_principal<K>(self) -> Self
+    where
+        K: Key,
+        K::Value: Display,
+    {
+        self.by(|ctx: &Context| match ctx.try_get::<K>() {
+            Some(principal) => Some(format!("principal:{}", principal)),
+            None => ctx.remote_addr().map(|addr| addr.ip().to_string()),
+        })
+    }
+
+    /// Tells clients apart by the key `key` returns for a request.
+    ///
+    /// Requests for which `key` returns `None` are not limited.
+    pub fn by<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Context) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// Keeps the quotas of clients in `store`.
+    pub fn store<S: Store + 'static>(mut self, store: S) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("quota", &self.quota)
+            .field(
+                "routes",
+                &self.routes
+                    .iter()
+                    .map(|&(ref pattern, quota)| (pattern.source(), quota))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Middleware for RateLimit {
+    fn call(&self, ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let client = match (self.key)(&ctx) {
+            Some(client) => client,
+            None => return next.call(ctx),
+        };
+
+        let (scope, quota) = self.routes
+            .iter()
+            .find(|&&(ref pattern, _)| pattern.is_match(ctx.path()))
+            .map_or(("*", &self.quota), |&(ref pattern, ref quota)| {
+                (pattern.source(), quota)
+            });
+
+        let key = format!("{} {}", scope, client);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+
+        self.store
+            .acquire(&key, quota, now)
+            .then(move |result| match result {
+                Ok(decision) => {
+                    if let Some(retry_after) = decision.retry_after {
+                        let mut response = Response::with(StatusCode::TooManyRequests);
+                        response
+                            .headers_mut()
+                            .set(RetryAfter::Delay(Duration::from_secs(seconds(retry_after))));
+
+                        set_headers(response.headers_mut(), &decision);
+
+                        return future::ok(response).into_box();
+                    }
+
+                    next.call(ctx)
+                        .map(move |mut response| {
+                            set_headers(response.headers_mut(), &decision);
+                            response
+                        })
+                        .into_box()
+                }
+
+                Err(err) => {
+                    // Requests are allowed while the store is unavailable
+                    warn!("failed to check rate limit: {}", err);
+
+                    next.call(ctx)
+                }
+            })
+            .into_box()
+    }
+}
+
+fn set_headers(headers: &mut Headers, decision: &Decision) {
+    headers.set_raw("RateLimit-Limit", decision.limit.to_string());
+    headers.set_raw("RateLimit-Remaining", decision.remaining.to_string());
+    headers.set_raw("RateLimit-Reset", seconds(decision.reset).to_string());
+}
+
+/// `duration` in whole seconds, rounded up.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use hyper;
+    use hyper::header::parsing;
+
+    use {Context, Response};
+    use ext::BoxFuture;
+    use http::StatusCode;
+    use http::header::{Formatter, Header, Raw, RetryAfter};
+    use state::Key;
+    use test::{TestClient, TestResponse};
+    use super::super::{Next, Stack};
+    use super::{Quota, RateLimit};
+
+    #[test]
+    fn test_check() {
+        let quota = Quota::per_second(2);
+        let now = Duration::from_secs(100);
+
+        let (first, tat) = quota.check(None, now);
+        assert!(first.is_allowed());
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset, Duration::from_millis(500));
+
+        let (second, tat) = quota.check(Some(tat), now);
+        assert!(second.is_allowed());
+        assert_eq!(second.remaining, 0);
+
+        let (third, tat) = quota.check(Some(tat), now);
+        assert!(!third.is_allowed());
+        assert_eq!(third.retry_after, Some(Duration::from_millis(500)));
+        assert_eq!(third.reset, Duration::from_secs(1));
+
+        // Half a period later, one request is available again
+        let later = now + Duration::from_millis(500);
+        let (fourth, _) = quota.check(Some(tat), later);
+        assert!(fourth.is_allowed());
+        assert_eq!(fourth.remaining, 0);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let client = TestClient::new(
+            Stack::new(|_: Context| Response::new())
+                .with(RateLimit::new(Quota::per_minute(2)).route("/login", Quota::per_hour(1))),
+        );
+
+        let alice: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let bob: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+
+        let response = client.get("/").remote_addr(alice).send();
+        response.assert_status(StatusCode::Ok);
+        assert_eq!(header(&response, "RateLimit-Limit"), "2");
+        assert_eq!(header(&response, "RateLimit-Remaining"), "1");
+        assert_eq!(header(&response, "RateLimit-Reset"), "30");
+
+        client.get("/").remote_addr(alice).send().assert_status(StatusCode::Ok);
+
+        let response = client.get("/").remote_addr(alice).send();
+        response
+            .assert_status(StatusCode::TooManyRequests)
+            .assert_header(&RetryAfter::Delay(Duration::from_secs(30)));
+        assert_eq!(header(&response, "RateLimit-Remaining"), "0");
+
+        // Other clients, and other routes, have their own quotas
+        client.get("/").remote_addr(bob).send().assert_status(StatusCode::Ok);
+        client.get("/login").remote_addr(alice).send().assert_status(StatusCode::Ok);
+        client
+            .get("/login")
+            .remote_addr(alice)
+            .send()
+            .assert_status(StatusCode::TooManyRequests);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most one request per nanosecond")]
+    fn test_zero_interval() {
+        Quota::new(2, Duration::from_secs(0));
+    }
+
+    #[derive(Clone)]
+    struct ApiKey(String);
+
+    impl Header for ApiKey {
+        fn header_name() -> &'static str {
+            "X-Api-Key"
+        }
+
+        fn parse_header(raw: &Raw) -> hyper::Result<Self> {
+            parsing::from_one_raw_str(raw).map(ApiKey)
+        }
+
+        fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+            f.fmt_line(&self.0)
+        }
+    }
+
+    #[test]
+    fn test_custom_key() {
+        let limit = RateLimit::new(Quota::per_hour(1))
+            .by(|ctx: &Context| ctx.headers().get::<ApiKey>().map(|key| key.0.clone()));
+
+        let client = TestClient::new(Stack::new(|_: Context| Response::new()).with(limit));
+
+        let alice: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let bob: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+
+        // Requests with the same key share a quota, wherever they come from
+        client
+            .get("/")
+            .header(ApiKey("secret".into()))
+            .remote_addr(alice)
+            .send()
+            .assert_status(StatusCode::Ok);
+        client
+            .get("/")
+            .header(ApiKey("secret".into()))
+            .remote_addr(bob)
+            .send()
+            .assert_status(StatusCode::TooManyRequests);
+        client
+            .get("/")
+            .header(ApiKey("other".into()))
+            .send()
+            .assert_status(StatusCode::Ok);
+
+        // Requests without a key are not limited
+        client.get("/").send().assert_status(StatusCode::Ok);
+        client.get("/").send().assert_status(StatusCode::Ok);
+    }
+
+    struct User;
+
+    impl Key for User {
+        type Value = String;
+    }
+
+    #[test]
+    fn test_by_principal() {
+        // Stands in for authentication middleware
+        let authenticate = |mut ctx: Context, next: Next| -> BoxFuture<Response, hyper::Error> {
+            let user = ctx.headers().get::<ApiKey>().map(|key| key.0.clone());
+            if let Some(user) = user {
+                ctx.put::<User>(user);
+            }
+
+            next.call(ctx)
+        };
+
+        let client = TestClient::new(
+            Stack::new(|_: Context| Response::new())
+                .with(authenticate)
+                .with(RateLimit::new(Quota::per_hour(1)).by_principal::<User>()),
+        );
+
+        let alice: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let bob: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+
+        client
+            .get("/")
+            .header(ApiKey("alice".into()))
+            .remote_addr(alice)
+            .send()
+            .assert_status(StatusCode::Ok);
+        client
+            .get("/")
+            .header(ApiKey("alice".into()))
+            .remote_addr(bob)
+            .send()
+            .assert_status(StatusCode::TooManyRequests);
+
+        // Anonymous requests fall back to the IP address, apart from the principal's quota
+        client.get("/").remote_addr(alice).send().assert_status(StatusCode::Ok);
+        client
+            .get("/")
+            .remote_addr(alice)
+            .send()
+            .assert_status(StatusCode::TooManyRequests);
+    }
+
+    fn header<'a>(response: &'a TestResponse, name: &str) -> &'a str {
+        let raw = response.headers().get_raw(name).unwrap();
+        ::std::str::from_utf8(raw.one().unwrap()).unwrap()
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str;
+
+use futures::Future;
+use hyper;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::header::{Formatter, Header, Raw};
+use response::Response;
+use state::Key;
+use util::random;
+use super::{Middleware, Next};
+
+// The longest request ID accepted from a client
+const MAX_LEN: usize = 200;
+
+/// The ID of a request, and the `X-Request-Id` header that carries it.
+///
+/// [`RequestIds`] puts the ID of each request into the request state, where it may be read
+/// with `ctx.get::<RequestId>()`. The ID is also sent by the client returned from
+/// `Context::client`, so a request to another service can be correlated with the request
+/// that caused it.
+///
+/// An ID is 1 to 200 characters of ASCII letters, digits, and `-_.:/+=@`; anything else in
+/// the header fails to parse.
+///
+/// [`RequestIds`]: struct.RequestIds.html
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Generates a new, random `RequestId` of 32 hexadecimal digits.
+    pub fn generate() -> Self {
+        RequestId(format!("{:016x}{:016x}", random::u64(), random::u64()))
+    }
+
+    /// Constructs a `RequestId` from `id`, if it is a valid ID.
+    pub fn parse<S: Into<String>>(id: S) -> Option<Self> {
+        let id = id.into();
+        let is_valid = !id.is_empty() && id.len() <= MAX_LEN
+            && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:/+=@".contains(&b));
+
+        if is_valid {
+            Some(RequestId(id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the ID as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for RequestId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Key for RequestId {
+    type Value = Self;
+}
+
+impl Header for RequestId {
+    fn header_name() -> &'static str {
+        "X-Request-Id"
+    }
+
+    fn parse_header(raw: &Raw) -> hyper::Result<Self> {
+        raw.one()
+            .and_then(|line| str::from_utf8(line).ok())
+            .and_then(|line| RequestId::parse(line.trim()))
+            .ok_or(hyper::Error::Header)
+    }
+
+    fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+        f.fmt_line(self)
+    }
+}
+
+/// [`Middleware`] that gives every request a [`RequestId`].
+///
+/// The ID is taken from the `X-Request-Id` header of the request when it is valid, and is
+/// generated otherwise. It is put into the request state and sent back in the
+/// `X-Request-Id` header of the response.
+///
+/// ```rust
+/// # use shio::prelude::*;
+/// # use shio::middleware::RequestId;
+/// fn index(ctx: Context) -> Response {
+///     Response::with(format!("Your request ID is {}\n", ctx.get::<RequestId>()))
+/// }
+/// ```
+///
+/// [`Middleware`]: trait.Middleware.html
+/// [`RequestId`]: struct.RequestId.html
+#[derive(Clone, Debug)]
+pub struct RequestIds {
+    trust_incoming: bool,
+}
+
+impl RequestIds {
+    /// Constructs a new `RequestIds`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets whether an ID sent by the client is used.
+    ///
+    /// Defaults to `true`. Set to `false` when clients are untrusted and every request should
+    /// be given a fresh ID.
+    pub fn trust_incoming(mut self, trust_incoming: bool) -> Self {
+        self.trust_incoming = trust_incoming;
+        self
+    }
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        Self {
+            trust_incoming: true,
+        }
+    }
+}
+
+impl Middleware for RequestIds {
+    fn call(&self, mut ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let incoming = if self.trust_incoming {
+            ctx.headers().get::<RequestId>().cloned()
+        } else {
+            None
+        };
+
+        let id = incoming.unwrap_or_else(RequestId::generate);
+        ctx.put::<RequestId>(id.clone());
+
+        next.call(ctx)
+            .map(move |mut response| {
+                response.headers_mut().set(id);
+                response
+            })
+            .into_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Context, Response};
+    use test::TestClient;
+    use super::super::Stack;
+    use super::{RequestId, RequestIds};
+
+    fn echo(ctx: Context) -> Response {
+        Response::with(ctx.get::<RequestId>().to_string())
+    }
+
+    #[test]
+    fn test_generate() {
+        let a = RequestId::generate();
+        let b = RequestId::generate();
+
+        assert_eq!(a.len(), 32);
+        assert!(RequestId::parse(a.as_str()).is_some());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(RequestId::parse("req-1234:abc/DEF=").is_some());
+        assert!(RequestId::parse("").is_none());
+        assert!(RequestId::parse("has space").is_none());
+        assert!(RequestId::parse("a\"b").is_none());
+        assert!(RequestId::parse("x".repeat(201)).is_none());
+    }
+
+    #[test]
+    fn test_request_ids() {
+        let client = TestClient::new(Stack::new(echo).with(RequestIds::new()));
+
+        let response = client.get("/").send();
+        let id = response.header::<RequestId>().unwrap().clone();
+        response.assert_body(&id);
+
+        let incoming = RequestId::parse("upstream-1").unwrap();
+        client
+            .get("/")
+            .header(incoming.clone())
+            .send()
+            .assert_header(&incoming)
+            .assert_body("upstream-1");
+
+        let client = Stack::new(echo).with(RequestIds::new().trust_incoming(false));
+        let client = TestClient::new(client);
+        let response = client.get("/").header(incoming.clone()).send();
+
+        assert_ne!(response.header::<RequestId>(), Some(&incoming));
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::ops::Deref;
+
+use base64;
+use futures::Future;
+use hyper;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use response::Response;
+use router::Pattern;
+use state::Key;
+use util::random;
+use super::{Middleware, Next};
+
+// Replaced with the nonce of each request in the `Content-Security-Policy`
+const NONCE: &str = "{nonce}";
+
+/// The nonce of a request's `Content-Security-Policy`, for the `nonce` attribute of inline
+/// scripts and styles.
+///
+/// [`SecurityHeaders`] puts a new nonce into the request state for every request whose
+/// policy contains `{nonce}`, where templates may read it with `ctx.get::<CspNonce>()`.
+///
+/// [`SecurityHeaders`]: struct.SecurityHeaders.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        let mut bytes = [0; 16];
+        random::fill_secret(&mut bytes);
+
+        CspNonce(base64::encode(&bytes))
+    }
+
+    /// Returns the nonce as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for CspNonce {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CspNonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Key for CspNonce {
+    type Value = Self;
+}
+
+/// [`Middleware`] that adds security-related headers to every response.
+///
+/// By default, these are sent:
+///
+/// | Header                      | Value                                        |
+/// |-----------------------------|----------------------------------------------|
+/// | `Strict-Transport-Security` | `max-age=31536000; includeSubDomains`        |
+/// | `X-Content-Type-Options`    | `nosniff`                                    |
+/// | `X-Frame-Options`           | `DENY`                                       |
+/// | `Referrer-Policy`           | `strict-origin-when-cross-origin`            |
+/// | `Permissions-Policy`        | `camera=(), geolocation=(), microphone=()`   |
+/// | `Content-Security-Policy`   | `default-src 'self'; base-uri 'self';`       |
+/// |                             | `object-src 'none'; frame-ancestors 'none'`  |
+///
+/// Each may be changed, or left out with `None`. A header the handler already set is left
+/// alone, and a different policy may be sent for the routes that match a pattern. A route's
+/// policy replaces this one entirely; it starts from the defaults, not from the changes made
+/// here.
+///
+/// Any `{nonce}` in the `Content-Security-Policy` is replaced with a new [`CspNonce`] for
+/// each request:
+///
+/// ```rust,no_run
+/// # use shio::prelude::*;
+/// # use shio::middleware::{CspNonce, SecurityHeaders, Stack};
+/// fn index(ctx: Context) -> Response {
+///     Response::with(format!(
+///         "<script nonce=\"{}\">console.log('Hello')</script>",
+///         ctx.get::<CspNonce>()
+///     ))
+/// }
+///
+/// let mut router = shio::router::Router::new();
+/// router.add((Method::GET, "/", index));
+///
+/// let headers = SecurityHeaders::new()
+///     .content_security_policy(Some("default-src 'self'; script-src 'nonce-{nonce}'"))
+///     .route(
+///         "/embed/{id}",
+///         SecurityHeaders::new()
+///             .frame_options(None)
+///             .content_security_policy(Some("frame-ancestors https://example.com")),
+///     );
+///
+/// Shio::new(Stack::new(router).with(headers))
+///     .run(":7878")
+///     .unwrap();
+/// ```
+///
+/// [`Middleware`]: trait.Middleware.html
+/// [`CspNonce`]: struct.CspNonce.html
+pub struct SecurityHeaders {
+    headers: Vec<(&'static str, Option<String>)>,
+    routes: Vec<(Pattern, SecurityHeaders)>,
+}
+
+impl SecurityHeaders {
+    /// Constructs a new `SecurityHeaders` with the default headers.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the `Strict-Transport-Security` header, which tells browsers to only use HTTPS.
+    pub fn hsts(self, value: Option<&str>) -> Self {
+        self.header("Strict-Transport-Security", value)
+    }
+
+    /// Sets whether `X-Content-Type-Options: nosniff` is sent, which stops browsers from
+    /// guessing the type of a response.
+    pub fn content_type_options(self, nosniff: bool) -> Self {
+        self.header("X-Content-Type-Options", if nosniff { Some("nosniff") } else { None })
+    }
+
+    /// Sets the `X-Frame-Options` header, which controls whether older browsers may show the
+    /// response in a frame. Newer browsers use `frame-ancestors` in the
+    /// `Content-Security-Policy` instead.
+    pub fn frame_options(self, value: Option<&str>) -> Self {
+        self.header("X-Frame-Options", value)
+    }
+
+    /// Sets the `Referrer-Policy` header.
+    pub fn referrer_policy(self, value: Option<&str>) -> Self {
+        self.header("Referrer-Policy", value)
+    }
+
+    /// Sets the `Permissions-Policy` header, which controls the browser features a page may
+    /// use.
+    pub fn permissions_policy(self, value: Option<&str>) -> Self {
+        self.header("Permissions-Policy", value)
+    }
+
+    /// Sets the `Content-Security-Policy` header.
+    ///
+    /// Any `{nonce}` in `value` is replaced with the [`CspNonce`] of each request.
+    ///
+    /// [`CspNonce`]: struct.CspNonce.html
+    pub fn content_security_policy(self, value: Option<&str>) -> Self {
+        self.header("Content-Security-Policy", value)
+    }
+
+    /// Sends `headers` instead for requests whose path matches `pattern`, such as
+    /// `/embed/{id}`.
+    ///
+    /// The first matching pattern is used. None of the headers of this policy are sent for
+    /// such a request, so any change made to this policy that the route also needs, such as a
+    /// `Content-Security-Policy`, must be made to `headers` as well.
+    pub fn route<P: Into<Pattern>>(mut self, pattern: P, headers: SecurityHeaders) -> Self {
+        self.routes.push((pattern.into(), headers));
+        self
+    }
+
+    fn header(mut self, name: &'static str, value: Option<&str>) -> Self {
+        let value = value.map(str::to_owned);
+
+        match self.headers.iter_mut().find(|&&mut (header, _)| header == name) {
+            Some(header) => header.1 = value,
+            None => self.headers.push((name, value)),
+        }
+
+        self
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            headers: Vec::new(),
+            routes: Vec::new(),
+        }.hsts(Some("max-age=31536000; includeSubDomains"))
+            .content_type_options(true)
+            .frame_options(Some("DENY"))
+            .referrer_policy(Some("strict-origin-when-cross-origin"))
+            .permissions_policy(Some("camera=(), geolocation=(), microphone=()"))
+            .content_security_policy(Some(
+                "default-src 'self'; base-uri 'self'; object-src 'none'; frame-ancestors 'none'",
+            ))
+    }
+}
+
+impl fmt::Debug for SecurityHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecurityHeaders")
+            .field("headers", &self.headers)
+            .field(
+                "routes",
+                &self.routes
+                    .iter()
+                    .map(|&(ref pattern, ref headers)| (pattern.source(), headers))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn call(&self, mut ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let policy = self.routes
+            .iter()
+            .find(|&&(ref pattern, _)| pattern.is_match(ctx.path()))
+            .map_or(self, |&(_, ref headers)| headers);
+
+        let mut nonce = None;
+        let mut headers = Vec::new();
+
+        for &(name, ref value) in &policy.headers {
+            let value = match *value {
+                Some(ref value) => value,
+                None => continue,
+            };
+
+            if name == "Content-Security-Policy" && value.contains(NONCE) {
+                let nonce = nonce.get_or_insert_with(CspNonce::generate);
+                headers.push((name, value.replace(NONCE, nonce)));
+            } else {
+                headers.push((name, value.clone()));
+            }
+        }
+
+        if let Some(nonce) = nonce {
+            ctx.put::<CspNonce>(nonce);
+        }
+
+        next.call(ctx)
+            .map(move |mut response| {
+                for (name, value) in headers {
+                    if response.headers().get_raw(name).is_none() {
+                        response.headers_mut().set_raw(name, value);
+                    }
+                }
+
+                response
+            })
+            .into_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Context, Response};
+    use http::header::Headers;
+    use test::TestClient;
+    use super::super::Stack;
+    use super::{CspNonce, SecurityHeaders};
+
+    fn index(ctx: Context) -> Response {
+        let nonce = ctx.try_get::<CspNonce>().map_or("", |nonce| nonce.as_str());
+
+        if ctx.path() == "/custom" {
+            let mut response = Response::with(nonce.to_owned());
+            response.headers_mut().set_raw("X-Frame-Options", "SAMEORIGIN");
+            return response;
+        }
+
+        Response::with(nonce.to_owned())
+    }
+
+    fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+        headers
+            .get_raw(name)
+            .and_then(|raw| raw.one())
+            .map(|line| ::std::str::from_utf8(line).unwrap())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let client = TestClient::new(Stack::new(index).with(SecurityHeaders::new()));
+
+        let response = client.get("/").send();
+        let headers = response.headers();
+
+        assert_eq!(header(headers, "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(header(headers, "X-Frame-Options"), Some("DENY"));
+        assert_eq!(
+            header(headers, "Strict-Transport-Security"),
+            Some("max-age=31536000; includeSubDomains")
+        );
+        assert!(header(headers, "Content-Security-Policy").is_some());
+        response.assert_body("");
+
+        // A header set by the handler is kept
+        let response = client.get("/custom").send();
+        assert_eq!(header(response.headers(), "X-Frame-Options"), Some("SAMEORIGIN"));
+    }
+
+    #[test]
+    fn test_nonce_and_routes() {
+        let client = TestClient::new(
+            Stack::new(index).with(
+                SecurityHeaders::new()
+                    .content_security_policy(Some("script-src 'nonce-{nonce}'"))
+                    .route("/embed/{id}", SecurityHeaders::new().frame_options(None)),
+            ),
+        );
+
+        let response = client.get("/").send();
+        let nonce = response.text().to_owned();
+
+        assert_eq!(nonce.len(), 24);
+        assert_eq!(
+            header(response.headers(), "Content-Security-Policy"),
+            Some(&*format!("script-src 'nonce-{}'", nonce))
+        );
+        assert_ne!(client.get("/").send().text(), nonce);
+
+        // The route's policy replaces the whole policy, starting from the defaults
+        let response = client.get("/embed/1").send();
+        assert_eq!(header(response.headers(), "X-Frame-Options"), None);
+        assert_eq!(header(response.headers(), "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(
+            header(response.headers(), "Content-Security-Policy"),
+            Some("default-src 'self'; base-uri 'self'; object-src 'none'; frame-ancestors 'none'")
+        );
+        response.assert_body("");
+    }
+}
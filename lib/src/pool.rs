@@ -0,0 +1,888 @@
+//! An asynchronous pool of connections bound to a worker's event loop.
+//!
+//! Connections to services such as databases are usually bound to the event loop they were
+//! created on and so cannot be shared across worker threads. A [`Pool`] is created once per
+//! worker with [`Shio::manage_local`] and hands out connections on that worker's event loop.
+//!
+//! ```rust
+//! # extern crate futures;
+//! # extern crate shio;
+//! # extern crate tokio_core;
+//! # use futures::future;
+//! # use shio::prelude::*;
+//! # use shio::context::Key;
+//! # use shio::pool::{self, Builder, Manager, Pool};
+//! # use tokio_core::reactor::Handle;
+//! // A stand-in for a real database connection
+//! struct Connection;
+//!
+//! struct ConnectionManager;
+//!
+//! impl Manager for ConnectionManager {
+//!     type Connection = Connection;
+//!     type Error = shio::Error;
+//!
+//!     fn connect(&self, _: &Handle) -> BoxFuture<Connection, Self::Error> {
+//!         future::ok(Connection).into_box()
+//!     }
+//!
+//!     fn validate(&self, conn: Connection) -> BoxFuture<Connection, Self::Error> {
+//!         future::ok(conn).into_box()
+//!     }
+//! }
+//!
+//! struct Database;
+//!
+//! impl Key for Database {
+//!     type Value = Pool<ConnectionManager>;
+//! }
+//!
+//! fn index(ctx: Context) -> BoxFuture<Response, pool::Error<shio::Error>> {
+//!     ctx.local().get::<Database>().get()
+//!         .map(|_conn| Response::with("Hello World!\n"))
+//!         .into_box()
+//! }
+//!
+//! # fn main() {
+//! let config = Builder::new().max_size(8).min_idle(2);
+//!
+//! Shio::default()
+//!     .manage_local::<Database, _>(move |handle| config.build(ConnectionManager, handle))
+//!     .route((Method::GET, "/", index));
+//! # }
+//! ```
+//!
+//! [`Pool`]: struct.Pool.html
+//! [`Shio::manage_local`]: ../struct.Shio.html#method.manage_local
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future, Stream};
+use futures::future::Either;
+use futures::unsync::oneshot;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+
+use ext::{BoxFuture, FutureExt};
+
+/// A trait which provides connection-specific functionality to a [`Pool`].
+///
+/// [`Pool`]: struct.Pool.html
+pub trait Manager: 'static {
+    /// The connection type this manager deals with.
+    type Connection: 'static;
+
+    /// The error type returned when connecting or validating fails.
+    type Error: 'static;
+
+    /// Attempts to create a new connection on the event loop referenced by `handle`.
+    fn connect(&self, handle: &Handle) -> BoxFuture<Self::Connection, Self::Error>;
+
+    /// Determines if an idle connection is still usable before it is checked out.
+    fn validate(&self, conn: Self::Connection) -> BoxFuture<Self::Connection, Self::Error>;
+}
+
+/// An error that occurs while checking a connection out of a [`Pool`].
+///
+/// [`Pool`]: struct.Pool.html
+#[derive(Debug)]
+pub enum Error<E> {
+    /// No connection became available before the connection timeout elapsed.
+    Timeout,
+
+    /// The manager failed to establish a new connection.
+    Manager(E),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Timeout => f.write_str("timed out waiting for a connection"),
+            Error::Manager(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: StdError> StdError for Error<E> {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Timeout => "timed out waiting for a connection",
+            Error::Manager(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Timeout => None,
+            Error::Manager(ref err) => Some(err),
+        }
+    }
+}
+
+/// A builder for a [`Pool`].
+///
+/// A `Builder` is `Send + Sync` and may be used to build one pool for each worker thread.
+///
+/// [`Pool`]: struct.Pool.html
+#[derive(Clone, Debug)]
+pub struct Builder {
+    max_size: usize,
+    min_idle: usize,
+    connection_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    reap_interval: Duration,
+    test_on_checkout: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            min_idle: 0,
+            connection_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            reap_interval: Duration::from_secs(30),
+            test_on_checkout: true,
+        }
+    }
+}
+
+impl Builder {
+    /// Constructs a new `Builder` with the default configuration.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the maximum number of connections managed by the pool.
+    ///
+    /// Defaults to 10.
+    ///
+    /// # Panics
+    ///
+    /// If `max_size` is 0.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        assert!(max_size > 0, "max_size must be positive");
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the minimum number of idle connections the pool tries to maintain.
+    ///
+    /// A connection that fails to open is retried after `reap_interval`.
+    ///
+    /// Defaults to 0.
+    pub fn min_idle(mut self, min_idle: usize) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+
+    /// Sets how long a checkout waits for a connection before failing with `Error::Timeout`.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn connection_timeout(mut self, connection_timeout: Duration) -> Self {
+        self.connection_timeout = connection_timeout;
+        self
+    }
+
+    /// Sets how long a connection may sit idle in the pool before it is closed.
+    ///
+    /// Defaults to 10 minutes. `None` keeps idle connections open indefinitely.
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets how often the pool looks for idle connections to close, and retries the
+    /// connections it failed to open to satisfy `min_idle`.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn reap_interval(mut self, reap_interval: Duration) -> Self {
+        self.reap_interval = reap_interval;
+        self
+    }
+
+    /// Sets whether idle connections are passed to `Manager::validate` before being
+    /// checked out.
+    ///
+    /// Defaults to `true`.
+    pub fn test_on_checkout(mut self, test_on_checkout: bool) -> Self {
+        self.test_on_checkout = test_on_checkout;
+        self
+    }
+
+    /// Consumes a manager, returning a new `Pool` bound to the event loop referenced
+    /// by `handle`.
+    pub fn build<M: Manager>(&self, manager: M, handle: &Handle) -> Pool<M> {
+        let pool = Pool {
+            inner: Rc::new(Inner {
+                manager,
+                config: self.clone(),
+                handle: handle.clone(),
+                internals: RefCell::new(Internals {
+                    idle: VecDeque::new(),
+                    size: 0,
+                    pending: 0,
+                    waiters: VecDeque::new(),
+                }),
+            }),
+        };
+
+        // The reaper also retries the connections for `min_idle` that failed to open
+        if self.idle_timeout.is_some() || self.min_idle > 0 {
+            pool.spawn_reaper();
+        }
+
+        pool.replenish();
+        pool
+    }
+}
+
+/// The current status of a [`Pool`].
+///
+/// [`Pool`]: struct.Pool.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// The number of connections managed by the pool, including those being opened.
+    pub connections: usize,
+
+    /// The number of idle connections.
+    pub idle_connections: usize,
+}
+
+struct Idle<C> {
+    conn: C,
+    since: Instant,
+}
+
+type Waiter<M> = oneshot::Sender<
+    Result<<M as Manager>::Connection, Error<<M as Manager>::Error>>,
+>;
+
+struct Internals<M: Manager> {
+    idle: VecDeque<Idle<M::Connection>>,
+    // Connections that are open, checked out, or being opened
+    size: usize,
+    // Connections being opened to satisfy `min_idle`
+    pending: usize,
+    waiters: VecDeque<Waiter<M>>,
+}
+
+struct Inner<M: Manager> {
+    manager: M,
+    config: Builder,
+    handle: Handle,
+    internals: RefCell<Internals<M>>,
+}
+
+/// A pool of connections bound to a single event loop.
+///
+/// Cloning a `Pool` is cheap and results in a handle to the same pool.
+pub struct Pool<M: Manager> {
+    inner: Rc<Inner<M>>,
+}
+
+impl<M: Manager> Clone for Pool<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: Manager> Pool<M> {
+    /// Constructs a new `Pool` with the default configuration.
+    pub fn new(manager: M, handle: &Handle) -> Self {
+        Builder::new().build(manager, handle)
+    }
+
+    /// Returns a future that resolves to a connection checked out of the pool.
+    ///
+    /// An idle connection is used if one is available. Otherwise a new connection is
+    /// opened if the pool has not reached its maximum size, or the checkout waits for a
+    /// connection to be returned, up to the configured connection timeout.
+    pub fn get(&self) -> BoxFuture<Pooled<M>, Error<M::Error>> {
+        loop {
+            let idle = self.inner.internals.borrow_mut().idle.pop_back();
+            let idle = match idle {
+                Some(idle) => idle,
+                None => break,
+            };
+
+            if self.is_expired(&idle) {
+                self.release();
+                continue;
+            }
+
+            if !self.inner.config.test_on_checkout {
+                return future::ok(Pooled::new(self.clone(), idle.conn)).into_box();
+            }
+
+            let pool = self.clone();
+
+            return self.inner
+                .manager
+                .validate(idle.conn)
+                .then(move |result| match result {
+                    Ok(conn) => future::ok(Pooled::new(pool, conn)).into_box(),
+                    Err(_) => {
+                        // The connection is broken; let it go and try again
+                        pool.release();
+                        pool.get()
+                    }
+                })
+                .into_box();
+        }
+
+        let reserved = {
+            let mut internals = self.inner.internals.borrow_mut();
+            if internals.size < self.inner.config.max_size {
+                internals.size += 1;
+                true
+            } else {
+                false
+            }
+        };
+
+        if reserved {
+            let pool = self.clone();
+
+            return self.connect()
+                .then(move |result| match result {
+                    Ok(conn) => Ok(Pooled::new(pool, conn)),
+                    Err(err) => {
+                        pool.release();
+                        Err(err)
+                    }
+                })
+                .into_box();
+        }
+
+        let (sender, receiver) = oneshot::channel();
+
+        {
+            let mut internals = self.inner.internals.borrow_mut();
+            internals.waiters.retain(|waiter| !waiter.is_canceled());
+            internals.waiters.push_back(sender);
+        }
+
+        // NOTE: A timer can only fail to be created once the event loop is gone, at which
+        //       point no connection can ever be handed to us
+        let timeout = match Timeout::new(self.inner.config.connection_timeout, &self.inner.handle)
+        {
+            Ok(timeout) => timeout,
+            Err(_) => return future::err(Error::Timeout).into_box(),
+        };
+
+        let pool = self.clone();
+
+        receiver
+            .select2(timeout)
+            .then(move |result| match result {
+                Ok(Either::A((Ok(conn), _))) => Ok(Pooled::new(pool, conn)),
+                Ok(Either::A((Err(err), _))) => Err(err),
+                _ => Err(Error::Timeout),
+            })
+            .into_box()
+    }
+
+    /// Opens a new connection, failing with `Error::Timeout` if it takes longer than the
+    /// connection timeout.
+    fn connect(&self) -> BoxFuture<M::Connection, Error<M::Error>> {
+        let connect = self.inner
+            .manager
+            .connect(&self.inner.handle)
+            .map_err(Error::Manager);
+
+        let timeout = match Timeout::new(self.inner.config.connection_timeout, &self.inner.handle)
+        {
+            Ok(timeout) => timeout,
+            Err(_) => return future::err(Error::Timeout).into_box(),
+        };
+
+        connect
+            .select2(timeout)
+            .then(|result| match result {
+                Ok(Either::A((conn, _))) => Ok(conn),
+                Err(Either::A((err, _))) => Err(err),
+                _ => Err(Error::Timeout),
+            })
+            .into_box()
+    }
+
+    /// Returns the current status of the pool.
+    pub fn status(&self) -> Status {
+        let internals = self.inner.internals.borrow();
+
+        Status {
+            connections: internals.size,
+            idle_connections: internals.idle.len(),
+        }
+    }
+
+    fn is_expired(&self, idle: &Idle<M::Connection>) -> bool {
+        self.inner
+            .config
+            .idle_timeout
+            .map_or(false, |timeout| idle.since.elapsed() >= timeout)
+    }
+
+    /// Returns a connection to the pool, handing it to a waiting checkout if there is one.
+    fn put(&self, mut conn: M::Connection) {
+        loop {
+            let waiter = self.inner.internals.borrow_mut().waiters.pop_front();
+
+            match waiter {
+                Some(waiter) => match waiter.send(Ok(conn)) {
+                    Ok(()) => return,
+                    // The checkout timed out; try the next one
+                    Err(Ok(returned)) => conn = returned,
+                    Err(Err(_)) => unreachable!(),
+                },
+
+                None => {
+                    self.inner.internals.borrow_mut().idle.push_back(Idle {
+                        conn,
+                        since: Instant::now(),
+                    });
+
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Frees the slot of a connection that was closed, opening a new connection for a
+    /// waiting checkout if there is one.
+    fn release(&self) {
+        if !self.free() {
+            self.replenish();
+        }
+    }
+
+    /// Frees the slot of a connection, handing it to a waiting checkout if there is one.
+    ///
+    /// Returns whether the slot went to a waiting checkout.
+    fn free(&self) -> bool {
+        let waiter = {
+            let mut internals = self.inner.internals.borrow_mut();
+            internals.size -= 1;
+
+            let mut waiter = None;
+            while let Some(next) = internals.waiters.pop_front() {
+                if !next.is_canceled() {
+                    internals.size += 1;
+                    waiter = Some(next);
+                    break;
+                }
+            }
+
+            waiter
+        };
+
+        let waiter = match waiter {
+            Some(waiter) => waiter,
+            None => return false,
+        };
+
+        let pool = self.clone();
+        let connect = self.connect().then(move |result| {
+            match result {
+                Ok(conn) => {
+                    if let Err(Ok(conn)) = waiter.send(Ok(conn)) {
+                        // The checkout timed out; keep the connection for the next one
+                        pool.put(conn);
+                    }
+                }
+
+                Err(err) => {
+                    // The slot was never filled, whether or not the checkout is still waiting
+                    let _ = waiter.send(Err(err));
+                    pool.release();
+                }
+            }
+
+            Ok(())
+        });
+
+        self.inner.handle.spawn(connect);
+        true
+    }
+
+    /// Opens connections in the background until `min_idle` is satisfied.
+    fn replenish(&self) {
+        loop {
+            {
+                let mut internals = self.inner.internals.borrow_mut();
+                let idle = internals.idle.len() + internals.pending;
+
+                if idle >= self.inner.config.min_idle
+                    || internals.size >= self.inner.config.max_size
+                {
+                    return;
+                }
+
+                internals.size += 1;
+                internals.pending += 1;
+            }
+
+            let pool = self.clone();
+            let connect = self.connect().then(move |result| {
+                pool.inner.internals.borrow_mut().pending -= 1;
+
+                match result {
+                    Ok(conn) => pool.put(conn),
+                    Err(_) => {
+                        // Don't retry immediately; the reaper, which runs whenever `min_idle`
+                        // is set, tries again on its next run. A waiting checkout may still
+                        // use the slot.
+                        pool.free();
+                    }
+                }
+
+                Ok(())
+            });
+
+            self.inner.handle.spawn(connect);
+        }
+    }
+
+    /// Closes connections that have been idle for longer than the idle timeout, and opens
+    /// connections until `min_idle` is satisfied.
+    fn reap(&self) {
+        let expired = {
+            let mut internals = self.inner.internals.borrow_mut();
+            let mut expired = Vec::new();
+
+            // Connections are returned to the back so the oldest are at the front
+            while internals
+                .idle
+                .front()
+                .map_or(false, |idle| self.is_expired(idle))
+            {
+                expired.extend(internals.idle.pop_front());
+            }
+
+            internals.size -= expired.len();
+            expired
+        };
+
+        drop(expired);
+        self.replenish();
+    }
+
+    fn spawn_reaper(&self) {
+        let interval = match Interval::new(self.inner.config.reap_interval, &self.inner.handle) {
+            Ok(interval) => interval,
+            Err(err) => {
+                error!("failed to start pool reaper: {}", err);
+                return;
+            }
+        };
+
+        let inner = Rc::downgrade(&self.inner);
+        let reaper = interval
+            .take_while(move |_| {
+                Ok(inner
+                    .upgrade()
+                    .map(|inner| Pool { inner }.reap())
+                    .is_some())
+            })
+            .for_each(|_| Ok(()))
+            .map_err(|err| error!("pool reaper failed: {}", err));
+
+        self.inner.handle.spawn(reaper);
+    }
+}
+
+/// A connection checked out of a [`Pool`].
+///
+/// The connection is returned to the pool when this is dropped.
+///
+/// [`Pool`]: struct.Pool.html
+pub struct Pooled<M: Manager> {
+    pool: Pool<M>,
+    conn: Option<M::Connection>,
+}
+
+impl<M: Manager> Pooled<M> {
+    fn new(pool: Pool<M>, conn: M::Connection) -> Self {
+        Self {
+            pool,
+            conn: Some(conn),
+        }
+    }
+
+    /// Takes the connection out, for APIs that consume the connection and hand it back
+    /// when they complete.
+    ///
+    /// The connection should be given back with `Pooled::put`. If this is dropped without a
+    /// connection, the connection is assumed to be lost and its slot in the pool is freed.
+    ///
+    /// # Panics
+    ///
+    /// If the connection has already been taken.
+    pub fn take(&mut self) -> M::Connection {
+        self.conn.take().expect("connection already taken")
+    }
+
+    /// Puts back a connection previously taken with `Pooled::take`.
+    pub fn put(&mut self, conn: M::Connection) {
+        self.conn = Some(conn);
+    }
+}
+
+impl<M: Manager> Deref for Pooled<M> {
+    type Target = M::Connection;
+
+    /// # Panics
+    ///
+    /// If the connection has been taken.
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().expect("connection taken")
+    }
+}
+
+impl<M: Manager> DerefMut for Pooled<M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().expect("connection taken")
+    }
+}
+
+impl<M: Manager> Drop for Pooled<M> {
+    fn drop(&mut self) {
+        match self.conn.take() {
+            Some(conn) => self.pool.put(conn),
+            None => self.pool.release(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::future;
+    use tokio_core::reactor::{Core, Handle, Timeout};
+
+    use ext::{BoxFuture, FutureExt};
+    use super::{Builder, Error, Manager, Pool, Status};
+
+    #[derive(Clone, Default)]
+    struct FakeManager {
+        connects: Rc<Cell<usize>>,
+        broken: Rc<Cell<bool>>,
+        unreachable: Rc<Cell<bool>>,
+        hung: Rc<Cell<bool>>,
+    }
+
+    impl Manager for FakeManager {
+        type Connection = usize;
+        type Error = ();
+
+        fn connect(&self, _: &Handle) -> BoxFuture<usize, ()> {
+            if self.hung.get() {
+                return future::empty().into_box();
+            }
+
+            if self.unreachable.get() {
+                return future::err(()).into_box();
+            }
+
+            self.connects.set(self.connects.get() + 1);
+            future::ok(self.connects.get()).into_box()
+        }
+
+        fn validate(&self, conn: usize) -> BoxFuture<usize, ()> {
+            if self.broken.get() {
+                future::err(()).into_box()
+            } else {
+                future::ok(conn).into_box()
+            }
+        }
+    }
+
+    fn sleep(core: &mut Core, ms: u64) {
+        let timeout = Timeout::new(Duration::from_millis(ms), &core.handle()).unwrap();
+        core.run(timeout).unwrap();
+    }
+
+    #[test]
+    fn test_reuse_connection() {
+        let mut core = Core::new().unwrap();
+        let manager = FakeManager::default();
+        let pool = Pool::new(manager.clone(), &core.handle());
+
+        let conn = core.run(pool.get()).unwrap();
+        assert_eq!(*conn, 1);
+        drop(conn);
+
+        let conn = core.run(pool.get()).unwrap();
+        assert_eq!(*conn, 1);
+        assert_eq!(manager.connects.get(), 1);
+    }
+
+    #[test]
+    fn test_checkout_timeout() {
+        let mut core = Core::new().unwrap();
+        let pool = Builder::new()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(10))
+            .build(FakeManager::default(), &core.handle());
+
+        let _conn = core.run(pool.get()).unwrap();
+
+        match core.run(pool.get()) {
+            Err(Error::Timeout) => {}
+            _ => panic!("expected checkout to time out"),
+        }
+    }
+
+    #[test]
+    fn test_waiter_receives_returned_connection() {
+        let mut core = Core::new().unwrap();
+        let pool = Builder::new()
+            .max_size(1)
+            .build(FakeManager::default(), &core.handle());
+
+        let conn = core.run(pool.get()).unwrap();
+        let checkout = pool.get();
+        drop(conn);
+
+        assert_eq!(*core.run(checkout).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_failed_connect_frees_slot() {
+        let mut core = Core::new().unwrap();
+        let manager = FakeManager::default();
+        let pool = Builder::new()
+            .max_size(1)
+            .build(manager.clone(), &core.handle());
+
+        let mut conn = core.run(pool.get()).unwrap();
+        let checkout = pool.get();
+
+        // The lost connection's slot goes to the waiting checkout, which fails to connect
+        manager.unreachable.set(true);
+        conn.take();
+        drop(conn);
+
+        match core.run(checkout) {
+            Err(Error::Manager(())) => {}
+            _ => panic!("expected checkout to fail to connect"),
+        }
+
+        assert_eq!(pool.status().connections, 0);
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        let mut core = Core::new().unwrap();
+        let manager = FakeManager::default();
+        let pool = Builder::new()
+            .connection_timeout(Duration::from_millis(10))
+            .build(manager.clone(), &core.handle());
+
+        manager.hung.set(true);
+
+        match core.run(pool.get()) {
+            Err(Error::Timeout) => {}
+            _ => panic!("expected connect to time out"),
+        }
+
+        assert_eq!(pool.status().connections, 0);
+    }
+
+    #[test]
+    fn test_broken_connection_is_replaced() {
+        let mut core = Core::new().unwrap();
+        let manager = FakeManager::default();
+        let pool = Pool::new(manager.clone(), &core.handle());
+
+        drop(core.run(pool.get()).unwrap());
+        manager.broken.set(true);
+
+        let conn = core.run(pool.get()).unwrap();
+        assert_eq!(*conn, 2);
+        assert_eq!(pool.status().connections, 1);
+    }
+
+    #[test]
+    fn test_taken_connection_frees_slot() {
+        let mut core = Core::new().unwrap();
+        let pool = Pool::new(FakeManager::default(), &core.handle());
+
+        let mut conn = core.run(pool.get()).unwrap();
+        conn.take();
+        drop(conn);
+
+        assert_eq!(
+            pool.status(),
+            Status {
+                connections: 0,
+                idle_connections: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let mut core = Core::new().unwrap();
+        let manager = FakeManager::default();
+        let pool = Builder::new()
+            .idle_timeout(Some(Duration::from_millis(5)))
+            .reap_interval(Duration::from_millis(5))
+            .build(manager.clone(), &core.handle());
+
+        drop(core.run(pool.get()).unwrap());
+        assert_eq!(pool.status().idle_connections, 1);
+
+        sleep(&mut core, 50);
+        assert_eq!(pool.status().connections, 0);
+    }
+
+    #[test]
+    fn test_min_idle_retries_failed_connect() {
+        let mut core = Core::new().unwrap();
+        let manager = FakeManager::default();
+        manager.unreachable.set(true);
+
+        let pool = Builder::new()
+            .min_idle(1)
+            .idle_timeout(None)
+            .reap_interval(Duration::from_millis(5))
+            .build(manager.clone(), &core.handle());
+
+        sleep(&mut core, 1);
+        assert_eq!(pool.status().connections, 0);
+
+        manager.unreachable.set(false);
+
+        sleep(&mut core, 50);
+        assert_eq!(pool.status().idle_connections, 1);
+    }
+
+    #[test]
+    fn test_min_idle() {
+        let mut core = Core::new().unwrap();
+        let pool = Builder::new()
+            .min_idle(2)
+            .build(FakeManager::default(), &core.handle());
+
+        sleep(&mut core, 1);
+        assert_eq!(
+            pool.status(),
+            Status {
+                connections: 2,
+                idle_connections: 2,
+            }
+        );
+    }
+}
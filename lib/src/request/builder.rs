@@ -0,0 +1,102 @@
+use std::net::SocketAddr;
+
+use hyper::{self, HttpVersion, Uri};
+
+use request::Request;
+use http::Method;
+use http::header::{Header, Headers};
+
+/// An HTTP request builder.
+///
+/// This type can be used to construct a [`Request`] through a builder-like pattern, for
+/// example to dispatch an internal request through a [`Router`].
+///
+/// ```rust
+/// # use shio::request::{self, Request};
+/// # use shio::http::{header, Method};
+/// let request: Request = request::Builder::new()
+///     .method(Method::POST)
+///     .uri("/users".parse().unwrap())
+///     .header(header::ContentType::json())
+///     .into();
+/// ```
+///
+/// [`Request`]: struct.Request.html
+/// [`Router`]: ../router/struct.Router.html
+pub struct Builder {
+    inner: Request,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            inner: Request::new((
+                hyper::Method::Get,
+                Uri::default(),
+                HttpVersion::default(),
+                Headers::new(),
+            )),
+        }
+    }
+}
+
+impl Builder {
+    /// Creates a new default instance of `Builder` to construct a [`Request`].
+    ///
+    /// The default request is `GET /` over HTTP/1.1 with no headers.
+    ///
+    /// [`Request`]: struct.Request.html
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the HTTP method for this request.
+    #[inline]
+    pub fn method(mut self, method: Method) -> Self {
+        self.inner.method = method.to_hyper_method();
+        self
+    }
+
+    /// Set the URI for this request.
+    #[inline]
+    pub fn uri(mut self, uri: Uri) -> Self {
+        self.inner.uri = uri;
+        self
+    }
+
+    /// Set the HTTP version for this request.
+    #[inline]
+    pub fn version(mut self, version: HttpVersion) -> Self {
+        self.inner.version = version;
+        self
+    }
+
+    /// Appends a [`Header`] to this request.
+    ///
+    /// [`Header`]: ../http/header/trait.Header.html
+    #[inline]
+    pub fn header<H: Header>(mut self, header: H) -> Self {
+        self.inner.headers.set(header);
+        self
+    }
+
+    /// Set the address of the peer that sent this request.
+    #[inline]
+    pub fn remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.inner.remote_addr = Some(remote_addr);
+        self
+    }
+
+    /// Replace all headers of this request.
+    #[inline]
+    pub fn headers(mut self, headers: Headers) -> Self {
+        self.inner.headers = headers;
+        self
+    }
+}
+
+impl From<Builder> for Request {
+    fn from(builder: Builder) -> Self {
+        builder.inner
+    }
+}
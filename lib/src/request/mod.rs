@@ -1,4 +1,10 @@
 
+mod builder;
+
+pub use self::builder::Builder;
+
+use std::net::SocketAddr;
+
 use hyper::{self, Method};
 
 pub struct Request {
@@ -6,6 +12,7 @@ pub struct Request {
     uri: hyper::Uri,
     version: hyper::HttpVersion,
     headers: hyper::Headers,
+    remote_addr: Option<SocketAddr>,
 }
 
 impl Request {
@@ -17,9 +24,15 @@ impl Request {
             uri: components.1,
             version: components.2,
             headers: components.3,
+            remote_addr: None,
         }
     }
 
+    /// Creates a new builder-style object to manufacture a Request.
+    pub fn build() -> Builder {
+        Default::default()
+    }
+
     /// Returns a reference to the request HTTP version.
     #[inline]
     pub fn version(&self) -> &hyper::HttpVersion {
@@ -32,6 +45,11 @@ impl Request {
         &self.headers
     }
 
+    #[inline]
+    pub(crate) fn headers_mut(&mut self) -> &mut hyper::Headers {
+        &mut self.headers
+    }
+
     /// Returns a reference to the request HTTP method.
     #[inline]
     pub fn method(&self) -> &Method {
@@ -49,4 +67,18 @@ impl Request {
     pub fn path(&self) -> &str {
         self.uri.path()
     }
+
+    /// Returns the address of the peer that sent this request, if known.
+    ///
+    /// This is the address of the connection, which may belong to a proxy rather than
+    /// the client.
+    #[inline]
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    #[inline]
+    pub(crate) fn set_remote_addr(&mut self, remote_addr: Option<SocketAddr>) {
+        self.remote_addr = remote_addr;
+    }
 }
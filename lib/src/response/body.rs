@@ -0,0 +1,241 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc;
+use hyper::{self, Chunk};
+
+/// The body of a [`Response`], as a stream of chunks.
+///
+/// A `Body` may be constructed from bytes or text, from any [`Stream`] of chunks with
+/// `Body::wrap_stream`, or from a channel with `Body::channel`. Chunks are pulled from a
+/// stream only as fast as they can be written to the connection.
+///
+/// If a stream fails part way through, the connection is closed without completing the
+/// response so the client can tell the body is truncated.
+///
+/// [`Response`]: struct.Response.html
+/// [`Stream`]: https://docs.rs/futures/0.1/futures/stream/trait.Stream.html
+pub struct Body(Inner);
+
+enum Inner {
+    Hyper(hyper::Body),
+    Stream(Box<Stream<Item = Chunk, Error = hyper::Error>>),
+}
+
+impl Body {
+    /// Constructs an empty `Body`.
+    pub fn empty() -> Self {
+        Default::default()
+    }
+
+    /// Constructs a `Body` from a stream of chunks.
+    ///
+    /// ```rust
+    /// # extern crate futures;
+    /// # extern crate shio;
+    /// # use std::io;
+    /// # use futures::stream;
+    /// # use shio::Response;
+    /// # use shio::response::Body;
+    /// # fn main() {
+    /// let rows = vec!["id,name\n", "1,shio\n"].into_iter().map(Ok::<_, io::Error>);
+    /// let response = Response::build().body(Body::wrap_stream(stream::iter_result(rows)));
+    /// # }
+    /// ```
+    pub fn wrap_stream<S>(stream: S) -> Self
+    where
+        S: Stream + 'static,
+        S::Item: Into<Chunk>,
+        S::Error: Into<Box<StdError + Send + Sync>>,
+    {
+        Body(Inner::Stream(Box::new(stream.map(Into::into).map_err(|err| {
+            hyper::Error::Io(io::Error::new(io::ErrorKind::Other, err))
+        }))))
+    }
+
+    /// Constructs a `Body` that streams the chunks sent through the returned [`Sender`].
+    ///
+    /// The channel is bounded; sending waits until the previous chunk has been taken
+    /// from the channel for writing to the connection.
+    ///
+    /// [`Sender`]: struct.Sender.html
+    pub fn channel() -> (Sender, Self) {
+        let (sender, receiver) = mpsc::channel(0);
+
+        (
+            Sender { inner: sender },
+            Body(Inner::Stream(Box::new(receiver.then(|result| match result {
+                Ok(item) => item,
+                // An `mpsc::Receiver` never fails
+                Err(()) => unreachable!(),
+            })))),
+        )
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body(Inner::Hyper(hyper::Body::default()))
+    }
+}
+
+impl Stream for Body {
+    type Item = Chunk;
+    type Error = hyper::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.0 {
+            Inner::Hyper(ref mut body) => body.poll(),
+            Inner::Stream(ref mut stream) => stream.poll(),
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Body").finish()
+    }
+}
+
+impl From<hyper::Body> for Body {
+    fn from(body: hyper::Body) -> Self {
+        Body(Inner::Hyper(body))
+    }
+}
+
+impl From<Chunk> for Body {
+    fn from(chunk: Chunk) -> Self {
+        hyper::Body::from(chunk).into()
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        hyper::Body::from(bytes).into()
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Self {
+        hyper::Body::from(bytes).into()
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        hyper::Body::from(text).into()
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        hyper::Body::from(text).into()
+    }
+}
+
+/// The sending half of a [`Body`] channel.
+///
+/// A `Sender` is `Send` and may be moved to other threads or tasks to produce the body. The
+/// body ends when the `Sender` is dropped.
+///
+/// [`Body`]: struct.Body.html
+pub struct Sender {
+    inner: mpsc::Sender<Result<Chunk, hyper::Error>>,
+}
+
+impl Sender {
+    /// Aborts the body, closing the connection without completing the response.
+    pub fn abort(self) {
+        let err = io::Error::new(io::ErrorKind::Other, "response body aborted");
+
+        // A new sender is guaranteed a slot in the channel even if this one is waiting
+        let _ = self.inner.clone().try_send(Err(hyper::Error::Io(err)));
+    }
+}
+
+impl Sink for Sender {
+    type SinkItem = Chunk;
+    type SinkError = hyper::Error;
+
+    fn start_send(&mut self, chunk: Chunk) -> StartSend<Chunk, hyper::Error> {
+        match self.inner.start_send(Ok(chunk)) {
+            Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
+            Ok(AsyncSink::NotReady(Ok(chunk))) => Ok(AsyncSink::NotReady(chunk)),
+            Ok(AsyncSink::NotReady(Err(_))) => unreachable!(),
+            Err(_) => Err(closed()),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), hyper::Error> {
+        self.inner.poll_complete().map_err(|_| closed())
+    }
+
+    fn close(&mut self) -> Poll<(), hyper::Error> {
+        self.inner.close().map_err(|_| closed())?;
+        Ok(Async::Ready(()))
+    }
+}
+
+impl fmt::Debug for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish()
+    }
+}
+
+fn closed() -> hyper::Error {
+    hyper::Error::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "response body receiver dropped",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::thread;
+
+    use futures::{stream, Future, Sink, Stream};
+
+    use super::Body;
+
+    #[test]
+    fn test_wrap_stream() {
+        let chunks = vec![Ok("Hello "), Ok("World")];
+        let body = Body::wrap_stream(stream::iter_result::<_, _, io::Error>(chunks));
+
+        assert_eq!(&*body.concat2().wait().unwrap(), b"Hello World");
+    }
+
+    #[test]
+    fn test_wrap_stream_error() {
+        let chunks = vec![Ok("Hello "), Err(io::Error::new(io::ErrorKind::Other, "oops"))];
+        let body = Body::wrap_stream(stream::iter_result(chunks));
+
+        assert!(body.concat2().wait().is_err());
+    }
+
+    #[test]
+    fn test_channel() {
+        let (sender, body) = Body::channel();
+
+        let producer = thread::spawn(move || {
+            let chunks = vec!["a".into(), "b".into(), "c".into()];
+            let chunks = stream::iter_ok::<_, ::hyper::Error>(chunks);
+            sender.send_all(chunks).wait().map(|_| ()).unwrap();
+        });
+
+        assert_eq!(&*body.concat2().wait().unwrap(), b"abc");
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_channel_abort() {
+        let (sender, body) = Body::channel();
+        sender.abort();
+
+        assert!(body.concat2().wait().is_err());
+    }
+}
@@ -1,6 +1,4 @@
-use hyper::Body;
-
-use response::Response;
+use response::{Body, Response};
 use http::StatusCode;
 use http::header::Header;
 
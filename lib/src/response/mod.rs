@@ -1,6 +1,8 @@
+mod body;
 mod builder;
 mod responder;
 
+pub use self::body::{Body, Sender};
 pub use self::builder::Builder;
 pub use self::responder::Responder;
 
@@ -12,10 +14,13 @@ use hyper;
 
 use http::StatusCode;
 use http::header::Headers;
+use service::Upgrade;
 
 /// Represents an HTTP response.
 pub struct Response {
-    inner: hyper::Response<hyper::Body>,
+    inner: hyper::Response<Body>,
+    body: Body,
+    upgrade: Option<Upgrade>,
 }
 
 impl Response {
@@ -36,8 +41,23 @@ impl Response {
         responder.to_response()
     }
 
-    pub(crate) fn into_hyper_response(self) -> hyper::Response<hyper::Body> {
-        self.inner
+    /// Take over the connection with `upgrade` once this response has been written.
+    ///
+    /// Only honored for a `101 Switching Protocols` response.
+    pub(crate) fn set_upgrade(&mut self, upgrade: Upgrade) {
+        self.upgrade = Some(upgrade);
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        if self.status() == StatusCode::SwitchingProtocols {
+            self.upgrade.take()
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn into_hyper_response(self) -> hyper::Response<Body> {
+        self.inner.with_body(self.body)
     }
 
     /// Get the status.
@@ -55,14 +75,37 @@ impl Response {
     }
 
     /// Take the body.
-    pub fn body(self) -> hyper::Body {
-        self.inner.body()
+    ///
+    /// The body is a [`Body`] rather than a `hyper::Body`, as it may be an arbitrary stream;
+    /// it is a `Stream` of the same chunks and errors.
+    ///
+    /// [`Body`]: struct.Body.html
+    pub fn body(self) -> Body {
+        self.body
+    }
+
+    /// Take the body, leaving an empty body in its place.
+    ///
+    /// Useful for middleware that transforms the body of a response, such as compression.
+    pub fn take_body(&mut self) -> Body {
+        ::std::mem::replace(&mut self.body, Body::empty())
     }
 
     /// Set the body for this response.
+    ///
+    /// To stream the body, see [`Body::wrap_stream`] and [`Body::channel`].
+    ///
+    /// [`Body::wrap_stream`]: struct.Body.html#method.wrap_stream
+    /// [`Body::channel`]: struct.Body.html#method.channel
+    #[inline]
+    pub fn set_body<B: Into<Body>>(&mut self, body: B) {
+        self.body = body.into();
+    }
+
+    /// Get a reference to the headers.
     #[inline]
-    pub fn set_body<B: Into<hyper::Body>>(&mut self, body: B) {
-        self.inner.set_body(body.into());
+    pub fn headers(&self) -> &Headers {
+        self.inner.headers()
     }
 
     /// Get a mutable reference to the headers.
@@ -76,6 +119,8 @@ impl Default for Response {
     fn default() -> Self {
         Self {
             inner: hyper::Response::new(),
+            body: Body::empty(),
+            upgrade: None,
         }
     }
 }
@@ -3,7 +3,7 @@ use std::fmt;
 use hyper;
 use futures::{future, Future, IntoFuture};
 
-use response::Response;
+use response::{Body, Response};
 use http::StatusCode;
 use http::header::ContentLength;
 use ext::{BoxFuture, FutureExt};
@@ -86,6 +86,15 @@ impl Responder for Response {
     }
 }
 
+impl Responder for Body {
+    type Result = Response;
+
+    #[inline]
+    fn to_response(self) -> Self::Result {
+        Response::build().body(self)
+    }
+}
+
 impl<E, R> Responder for Box<Future<Item = R, Error = E>>
 where
     E: fmt::Debug + Send + 'static,
@@ -133,11 +142,12 @@ where
 #[cfg(test)]
 mod tests {
     use std::fmt;
+    use std::thread;
 
     use tokio_core::reactor::Core;
-    use futures::{Future, IntoFuture, Stream};
+    use futures::{stream, Future, IntoFuture, Sink, Stream};
 
-    use super::{Responder, Response, StatusCode};
+    use super::{Body, Responder, Response, StatusCode};
 
     fn to_response<R: Responder>(r: R) -> Response
     where
@@ -185,6 +195,18 @@ mod tests {
         assert_body(res, "Hello\n");
     }
 
+    #[test]
+    fn body_to_response() {
+        let (sender, body) = Body::channel();
+        let res = to_response(body);
+
+        let chunks = stream::iter_ok::<_, ::hyper::Error>(vec!["Hel".into(), "lo\n".into()]);
+        thread::spawn(move || sender.send_all(chunks).wait().unwrap());
+
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_body(res, "Hello\n");
+    }
+
     #[test]
     fn status_to_response() {
         let res = to_response(StatusCode::NoContent);
@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use context::Context;
+use state::Key;
+
+/// The [`Pattern`] of the route that matched a request, as it was written.
+///
+/// The [`Router`] puts this into the request state before calling the route's handler, where
+/// it may be read with `ctx.get::<RoutePattern>()`. Unlike the request path, it is the same
+/// for every request to a route, which makes it suitable as a label for metrics or traces.
+///
+/// [`Pattern`]: struct.Pattern.html
+/// [`Router`]: struct.Router.html
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RoutePattern(Arc<str>);
+
+impl RoutePattern {
+    pub(crate) fn new(source: Arc<str>) -> Self {
+        RoutePattern(source)
+    }
+
+    /// Returns the pattern as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for RoutePattern {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RoutePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Key for RoutePattern {
+    type Value = Self;
+}
+
+/// A place for the [`Router`] to record the matched pattern where middleware that ran
+/// before it can still see it, after the context has been given away.
+///
+/// [`Router`]: struct.Router.html
+#[derive(Clone, Default)]
+pub(crate) struct RouteSlot(Rc<RefCell<Option<RoutePattern>>>);
+
+impl RouteSlot {
+    /// Returns the slot in the request state, putting in a new one if there is none yet.
+    ///
+    /// Every middleware that wants the matched pattern shares the one slot, as the request
+    /// state holds a single value for each key and the router only fills that one.
+    pub(crate) fn install(ctx: &mut Context) -> Self {
+        if let Some(slot) = ctx.try_get::<RouteSlot>() {
+            return slot.clone();
+        }
+
+        let slot = RouteSlot::default();
+        ctx.put::<RouteSlot>(slot.clone());
+        slot
+    }
+
+    pub(crate) fn set(&self, pattern: RoutePattern) {
+        *self.0.borrow_mut() = Some(pattern);
+    }
+
+    pub(crate) fn get(&self) -> Option<RoutePattern> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Key for RouteSlot {
+    type Value = Self;
+}
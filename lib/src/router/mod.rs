@@ -1,10 +1,13 @@
 mod route;
 mod pattern;
 mod parameters;
+mod matched;
 
 pub use self::route::Route;
 pub use self::pattern::Pattern;
 pub use self::parameters::Parameters;
+pub use self::matched::RoutePattern;
+pub(crate) use self::matched::RouteSlot;
 
 use std::collections::HashMap;
 
@@ -105,6 +108,13 @@ impl Handler for Router {
     fn call(&self, mut ctx: Context) -> Self::Result {
         // let route = self.find(ctx.method(), ctx.path());
         if let Some(route) = self.find(ctx.method(), ctx.path()) {
+            let pattern = RoutePattern::new(route.pattern().shared_source());
+            if let Some(slot) = ctx.try_get::<RouteSlot>() {
+                slot.set(pattern.clone());
+            }
+
+            ctx.put::<RoutePattern>(pattern);
+
             // Re-parse the path to pull out captures
             if let Some(parameters) = route.pattern().parameters(ctx.path()) {
                 // Add the parameters to the request context
@@ -124,12 +134,14 @@ impl Handler for Router {
 
 #[cfg(test)]
 mod tests {
+    use futures::Stream;
     use tokio_core::reactor::Core;
     use hyper;
 
-    use super::{Parameters, Router};
-    use {Context, Handler, Response, State};
+    use super::{Parameters, RoutePattern, Router};
+    use {Context, Handler, Request, Response};
     use http::{Method, StatusCode};
+    use test::TestClient;
 
     // Empty handler to use for route tests
     fn empty_handler(_: Context) -> Response {
@@ -199,23 +211,29 @@ mod tests {
     fn test_param_get_value() {
         let mut router = Router::new();
         router.add((Method::GET, "/user/{id}", |context: Context| {
-            // FIXME: We should have an assert that we got here
             assert_eq!(&context.get::<Parameters>()["id"], "3289");
 
             Response::with(StatusCode::NoContent)
         }));
 
-        let mut core = Core::new().unwrap();
-
-        // TODO: It should much easier to make a test context
-        //       Perhaps `Request::build ( .. )` should be a thing?
-        //       Proxied as `Context::build ( .. )` ?
-        let (request, data) = ::service::from_hyper_request(hyper::Request::new(hyper::Method::Get, "/user/3289".parse().unwrap()));
-        let context = Context::new(core.handle(), request, State::default(), data);
+        TestClient::new(router)
+            .get("/user/3289")
+            .send()
+            .assert_status(StatusCode::NoContent);
+    }
 
-        let work = router.call(context);
+    /// Test for the matched pattern in the request state
+    #[test]
+    fn test_route_pattern() {
+        let mut router = Router::new();
+        router.add((Method::GET, "/user/{id}", |context: Context| {
+            Response::with(context.get::<RoutePattern>().to_string())
+        }));
 
-        core.run(work).unwrap();
+        TestClient::new(router)
+            .get("/user/3289")
+            .send()
+            .assert_body("/user/{id}");
     }
 
     /// Test for some match for a custom parameter
@@ -235,7 +253,6 @@ mod tests {
     fn test_param_get_custom() {
         let mut router = Router::new();
         router.add((Method::GET, "/static/{filename: .*}", |context: Context| {
-            // FIXME: We should have an assert that we got here
             assert_eq!(
                 &context.get::<Parameters>()["filename"],
                 "path/to/file/is/here"
@@ -244,13 +261,34 @@ mod tests {
             Response::with(StatusCode::NoContent)
         }));
 
-        let mut core = Core::new().unwrap();
-
-        let (request, data) = ::service::from_hyper_request(hyper::Request::new(hyper::Method::Get, "/static/path/to/file/is/here".parse().unwrap()));
-        let context = Context::new(core.handle(), request, State::default(), data);
+        TestClient::new(router)
+            .get("/static/path/to/file/is/here")
+            .send()
+            .assert_status(StatusCode::NoContent);
+    }
 
-        let work = router.call(context);
+    /// Test for dispatching a manually built context
+    #[test]
+    fn test_call_built_context() {
+        let mut router = Router::new();
+        router.add((Method::PUT, "/jobs/{id}", |context: Context| {
+            Response::with(format!("job {}", &context.get::<Parameters>()["id"]))
+        }));
 
-        core.run(work).unwrap();
+        let mut core = Core::new().unwrap();
+        let context = Context::build(&core.handle())
+            .request(
+                Request::build()
+                    .method(Method::PUT)
+                    .uri("/jobs/42".parse().unwrap())
+                    .into(),
+            )
+            .into();
+
+        let response = core.run(router.call(context)).unwrap();
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        let body = core.run(response.body().concat2()).unwrap();
+        assert_eq!(&*body, b"job 42");
     }
 }
@@ -10,6 +10,7 @@ use super::Parameters;
 pub struct Pattern {
     re: Regex,
     names: Arc<HashMap<String, usize>>,
+    source: Arc<str>,
 }
 
 impl Pattern {
@@ -20,11 +21,23 @@ impl Pattern {
             .collect();
 
         Self {
+            source: Arc::from(re.as_str()),
             re,
             names: Arc::new(names),
         }
     }
 
+    /// Returns the pattern as it was written, such as `/user/{id}`.
+    ///
+    /// For a pattern constructed from a `Regex`, this is the regular expression.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub(crate) fn shared_source(&self) -> Arc<str> {
+        self.source.clone()
+    }
+
     pub(crate) fn parameters(&self, text: &str) -> Option<Parameters> {
         let captures = match self.re.captures(text) {
             Some(captures) => captures,
@@ -47,7 +60,10 @@ impl FromStr for Pattern {
     type Err = RegexError;
 
     fn from_str(pattern: &str) -> Result<Self, Self::Err> {
-        Ok(Pattern::new(Regex::new(&parse(pattern))?))
+        let mut compiled = Pattern::new(Regex::new(&parse(pattern))?);
+        compiled.source = Arc::from(pattern);
+
+        Ok(compiled)
     }
 }
 
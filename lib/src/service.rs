@@ -1,38 +1,77 @@
+//! A hyper [`Service`] that dispatches requests to a Shio [`Handler`].
+//!
+//! [`Service`]: https://docs.rs/hyper/0.11/hyper/server/trait.Service.html
+//! [`Handler`]: ../trait.Handler.html
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::fmt;
 use std::panic::AssertUnwindSafe;
 
-use hyper;
+use bytes::Bytes;
+use hyper::{self, Chunk};
+use hyper::server::Http;
 use tokio_core::reactor::Handle;
-use futures::{future, Future, IntoFuture};
+use tokio_io::{AsyncRead, AsyncWrite};
+use futures::{future, Async, Future, IntoFuture};
+use futures::future::Shared;
 use unsafe_any::UnsafeAny;
 
 use request::Request;
+use response::{Body, Response};
 use handler::{default_catch, Handler};
 use context::Context;
 use state::State;
 use util::typemap::TypeMap;
-use ext::BoxFuture;
+use client;
+use metrics::Metrics;
+use ext::{BoxFuture, FutureExt};
 use Data;
 
+/// A connection that has been taken over from HTTP.
+pub(crate) trait Io: AsyncRead + AsyncWrite {}
+
+impl<T: AsyncRead + AsyncWrite> Io for T {}
+
+/// Takes over a connection once a `101 Switching Protocols` response has been written,
+/// along with any bytes the client sent after its request.
+pub(crate) type Upgrade = Box<FnOnce(Box<Io>, Bytes)>;
+
 // FIXME: Why does #[derive(Clone)] not work here? This _seems_ like a implementation that
 //        should be auto-derived.
 
+/// Adapts a [`Handler`] and its shared state into a hyper [`Service`].
+///
+/// A `Service` is bound to a single event loop; each request is given a [`Context`]
+/// referencing that loop's `Handle`.
+///
+/// [`Handler`]: ../trait.Handler.html
+/// [`Context`]: ../context/struct.Context.html
+/// [`Service`]: https://docs.rs/hyper/0.11/hyper/server/trait.Service.html
 // #[derive(Clone)]
-pub(crate) struct Service<H: Handler + 'static>
+pub struct Service<H: Handler + 'static>
 where
     <H::Result as IntoFuture>::Error: fmt::Debug + Send,
 {
     handler: Arc<H>,
     handle: Handle,
     shared_state: Arc<TypeMap<UnsafeAny + Send + Sync>>,
+    local_state: Rc<TypeMap>,
+    upgrade: Rc<RefCell<Option<Upgrade>>>,
+    remote_addr: Option<SocketAddr>,
+    metrics: Option<Metrics>,
+    shutdown: Option<Shared<BoxFuture<(), ()>>>,
 }
 
 impl<H: Handler + 'static> Service<H>
 where
     <H::Result as IntoFuture>::Error: fmt::Debug + Send,
 {
-    pub(crate) fn new(
+    /// Constructs a new `Service` from a handler, the `Handle` of the event loop it will be run
+    /// on, and the state to be shared across all requests.
+    pub fn new(
         handler: Arc<H>,
         handle: Handle,
         shared_state: Arc<TypeMap<UnsafeAny + Send + Sync>>,
@@ -41,7 +80,105 @@ where
             handler,
             handle,
             shared_state,
+            local_state: Rc::new(TypeMap::new()),
+            upgrade: Rc::new(RefCell::new(None)),
+            remote_addr: None,
+            metrics: None,
+            shutdown: None,
+        }.local_state(TypeMap::new())
+    }
+
+    /// Sets the state shared by all requests handled by this `Service`.
+    ///
+    /// This state is never sent across threads and so may hold values bound to the
+    /// event loop of this `Service`.
+    pub fn local_state(mut self, mut local_state: TypeMap) -> Self {
+        if !local_state.has::<client::Lazy>() {
+            local_state.put::<client::Lazy>(client::Lazy::new(
+                client::Builder::new(),
+                self.handle.clone(),
+            ));
         }
+
+        self.local_state = Rc::new(local_state);
+        self
+    }
+
+    /// Sets the address of the peer of the connection this `Service` serves.
+    ///
+    /// The address is available to handlers through [`Request::remote_addr`].
+    ///
+    /// [`Request::remote_addr`]: ../request/struct.Request.html#method.remote_addr
+    pub fn remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+
+    /// Sets the [`Metrics`] that count the connections served by this `Service`.
+    ///
+    /// [`Metrics`]: ../metrics/struct.Metrics.html
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets a future that completes when the server begins to shut down, after which
+    /// connections are closed as soon as they are idle.
+    pub(crate) fn shutdown(mut self, signal: Shared<BoxFuture<(), ()>>) -> Self {
+        self.shutdown = Some(signal);
+        self
+    }
+
+    /// Serves HTTP on a single connection until it is closed.
+    ///
+    /// Unlike serving this `Service` with hyper directly, a connection served this way may
+    /// be taken over by a handler that answers with `101 Switching Protocols`, such as a
+    /// [`WebSocket`].
+    ///
+    /// [`WebSocket`]: ../websocket/struct.WebSocket.html
+    pub fn serve_connection<I>(&self, io: I) -> BoxFuture<(), hyper::Error>
+    where
+        I: AsyncRead + AsyncWrite + 'static,
+    {
+        // Each connection needs its own slot for a pending upgrade
+        let mut service = self.clone();
+        service.upgrade = Rc::new(RefCell::new(None));
+
+        let open = self.metrics.as_ref().map(Metrics::connection);
+        let mut connection = Some(Http::<Chunk>::new().serve_connection(io, service));
+        let mut shutdown = self.shutdown.clone();
+
+        future::poll_fn(move || {
+            let stopping = match shutdown {
+                Some(ref mut shutdown) => match shutdown.poll() {
+                    Ok(Async::NotReady) => false,
+                    _ => true,
+                },
+
+                None => false,
+            };
+
+            if stopping {
+                // Close the connection if it is idle, or once the response in flight is sent
+                shutdown = None;
+                connection.as_mut().unwrap().disable_keep_alive();
+            }
+
+            try_ready!(connection.as_mut().unwrap().poll_without_shutdown());
+
+            let parts = connection.take().unwrap().into_parts();
+            let upgrade = parts.service.upgrade.borrow_mut().take();
+
+            if let Some(upgrade) = upgrade {
+                upgrade(Box::new(parts.io), parts.read_buf);
+            }
+
+            Ok(Async::Ready(()))
+        }).then(move |result| {
+            // Counted as open until the connection is closed or taken over
+            drop(open);
+            result
+        }).into_box()
     }
 }
 
@@ -54,6 +191,11 @@ where
             handler: self.handler.clone(),
             handle: self.handle.clone(),
             shared_state: self.shared_state.clone(),
+            local_state: self.local_state.clone(),
+            upgrade: self.upgrade.clone(),
+            remote_addr: self.remote_addr,
+            metrics: self.metrics.clone(),
+            shutdown: self.shutdown.clone(),
         }
     }
 }
@@ -66,33 +208,48 @@ pub(crate) fn from_hyper_request(request: hyper::Request) -> (Request, Data) {
     )
 }
 
+/// Call `handler`, turning errors and panics into a response with `default_catch`.
+pub(crate) fn dispatch<H: Handler + 'static>(
+    handler: Arc<H>,
+    ctx: Context,
+) -> BoxFuture<Response, hyper::Error>
+where
+    <H::Result as IntoFuture>::Error: fmt::Debug + Send,
+{
+    Box::new(
+        AssertUnwindSafe(future::lazy(move || handler.call(ctx).into_future()))
+            .catch_unwind()
+            .then(|result| -> BoxFuture<Response, hyper::Error> {
+                Box::new(future::ok(match result {
+                    Err(err) => default_catch(err),
+                    Ok(Err(err)) => default_catch(err),
+                    Ok(Ok(response)) => response,
+                }))
+            }),
+    )
+}
+
 impl<H: Handler + 'static> hyper::server::Service for Service<H>
 where
     <H::Result as IntoFuture>::Error: fmt::Debug + Send,
 {
     type Request = hyper::Request;
-    type Response = hyper::Response;
+    type Response = hyper::Response<Body>;
     type Error = hyper::Error;
     type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
 
     fn call(&self, request: Self::Request) -> Self::Future {
-        let (request, data) = from_hyper_request(request);
-        let state = State::new(self.shared_state.clone());
+        let (mut request, data) = from_hyper_request(request);
+        request.set_remote_addr(self.remote_addr);
+
+        let state = State::new(self.shared_state.clone(), self.local_state.clone());
         let ctx = Context::new(self.handle.clone(), request, state, data);
-        let handler = self.handler.clone();
-
-        Box::new(
-            AssertUnwindSafe(future::lazy(move || handler.call(ctx).into_future()))
-                .catch_unwind()
-                .then(|result| -> BoxFuture<hyper::Response, hyper::Error> {
-                    Box::new(future::ok(
-                        (match result {
-                            Err(err) => default_catch(err),
-                            Ok(Err(err)) => default_catch(err),
-                            Ok(Ok(response)) => response,
-                        }).into_hyper_response(),
-                    ))
-                }),
-        )
+
+        let upgrade = self.upgrade.clone();
+
+        Box::new(dispatch(self.handler.clone(), ctx).map(move |mut response| {
+            *upgrade.borrow_mut() = response.take_upgrade();
+            response.into_hyper_response()
+        }))
     }
 }
@@ -1,13 +1,18 @@
-use std::sync::Arc;
-use std::thread::{self, JoinHandle};
-use std::fmt;
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::io;
 use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use num_cpus;
-use futures::{future, IntoFuture, Stream};
-use hyper::server::Http;
+use futures::{future, Future, IntoFuture, Stream};
+use futures::future::Either;
+use futures::sync::oneshot;
 use tokio_core::net::TcpListener;
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Handle};
 use net2::TcpBuilder;
 use util::typemap::{Key, TypeMap};
 use unsafe_any::UnsafeAny;
@@ -18,8 +23,14 @@ use net2::unix::UnixTcpBuilderExt;
 use handler::Handler;
 use router::{Route, Router};
 use errors::ListenError;
-use ext::ToSocketAddrsExt;
+use ext::{BoxFuture, FutureExt, ToSocketAddrsExt};
 use service::Service;
+use client;
+use context::Context;
+use health::Health;
+use metrics::Metrics;
+
+type LocalStateFactory = Fn(&Handle, &mut TypeMap) + Send + Sync;
 
 pub struct Shio<H: Handler + 'static>
 where
@@ -28,6 +39,12 @@ where
     handler: Arc<H>,
     threads: usize,
     shared_state: Arc<TypeMap<UnsafeAny + Send + Sync>>,
+    local_state: Vec<Arc<LocalStateFactory>>,
+    client: client::Builder,
+    metrics: Option<Metrics>,
+    health: Health,
+    drain_delay: Duration,
+    shutdown_timeout: Duration,
 }
 
 impl<H: Handler> Shio<H>
@@ -35,10 +52,20 @@ where
     <H::Result as IntoFuture>::Error: fmt::Debug + Send,
 {
     pub fn new(handler: H) -> Self {
+        let health = Health::new();
+        let mut shared_state = TypeMap::custom();
+        shared_state.put::<Health>(health.clone());
+
         Self {
             handler: Arc::new(handler),
             threads: num_cpus::get(),
-            shared_state: Arc::new(TypeMap::custom()),
+            shared_state: Arc::new(shared_state),
+            local_state: Vec::new(),
+            client: client::Builder::new(),
+            metrics: None,
+            health,
+            drain_delay: Duration::from_secs(0),
+            shutdown_timeout: Duration::from_secs(30),
         }
     }
 
@@ -51,64 +78,290 @@ where
         self
     }
 
+    /// Add a factory for worker-local state.
+    ///
+    /// The factory is run once on each worker thread, with the `Handle` of that thread's event
+    /// loop, before any requests are accepted. The value it returns is available to every
+    /// request handled by that thread through `Context::local`. Unlike `manage`, the value
+    /// need not be `Send` or `Sync`, which allows keeping resources such as database
+    /// connections that are bound to a specific event loop.
+    ///
+    /// ```rust
+    /// # use std::cell::Cell;
+    /// # use shio::prelude::*;
+    /// # use shio::context::Key;
+    /// struct RequestCount;
+    ///
+    /// impl Key for RequestCount {
+    ///     type Value = Cell<usize>;
+    /// }
+    ///
+    /// Shio::default()
+    ///     .manage_local::<RequestCount, _>(|_| Cell::new(0))
+    ///     .route((Method::GET, "/", |ctx: Context| {
+    ///         let count = ctx.local().get::<RequestCount>();
+    ///         count.set(count.get() + 1);
+    ///
+    ///         Response::with(format!("{}\n", count.get()))
+    ///     }));
+    /// ```
+    pub fn manage_local<K, F>(&mut self, factory: F) -> &mut Self
+    where
+        K: Key,
+        F: Fn(&Handle) -> K::Value + Send + Sync + 'static,
+    {
+        self.local_state.push(Arc::new(move |handle: &Handle, local_state: &mut TypeMap| {
+            local_state.put::<K>(factory(handle));
+        }));
+
+        self
+    }
+
+    /// Configure the outbound HTTP client returned by `Context::client`.
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use shio::prelude::*;
+    /// # use shio::client;
+    /// Shio::default().client(
+    ///     client::Builder::new()
+    ///         .timeout(Some(Duration::from_secs(5)))
+    ///         .user_agent(Some("my-service/1.0")),
+    /// );
+    /// ```
+    pub fn client(&mut self, client: client::Builder) -> &mut Self {
+        self.client = client;
+        self
+    }
+
+    /// Count open connections and worker respawns in `metrics`.
+    ///
+    /// Requests are counted by adding the same [`Metrics`] as middleware.
+    ///
+    /// [`Metrics`]: metrics/struct.Metrics.html
+    pub fn metrics(&mut self, metrics: &Metrics) -> &mut Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Returns the [`Health`] whose checks are run by the probe handlers.
+    ///
+    /// [`Health`]: health/struct.Health.html
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    /// Register a readiness check, run by [`health::readiness`].
+    ///
+    /// [`health::readiness`]: health/fn.readiness.html
+    pub fn health_check<F, R>(&mut self, name: &str, check: F) -> &mut Self
+    where
+        F: Fn(&Context) -> R + Send + Sync + 'static,
+        R: IntoFuture<Item = ()>,
+        R::Future: 'static,
+        R::Error: Display,
+    {
+        self.health.check(name, check);
+        self
+    }
+
+    /// Set how long readiness fails before a graceful shutdown stops accepting connections.
+    ///
+    /// This gives load balancers time to notice and stop sending new requests. Defaults to
+    /// zero.
+    pub fn drain_delay(&mut self, delay: Duration) -> &mut Self {
+        self.drain_delay = delay;
+        self
+    }
+
+    /// Set how long a graceful shutdown waits for open connections to close.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
     /// Set the number of threads to use.
     pub fn threads(&mut self, threads: usize) {
         self.threads = threads;
     }
 
-    #[cfg_attr(feature = "cargo-clippy", allow(use_debug, never_loop))]
+    /// Returns a hyper [`Service`] that dispatches requests to the root handler on the event
+    /// loop referenced by `handle`.
+    ///
+    /// This may be used to serve connections accepted by some other means.
+    ///
+    /// [`Service`]: service/struct.Service.html
+    pub fn service(&self, handle: &Handle) -> Service<H> {
+        let service = Service::new(
+            self.handler.clone(),
+            handle.clone(),
+            self.shared_state.clone(),
+        ).local_state(build_local_state(&self.local_state, &self.client, handle));
+
+        match self.metrics {
+            Some(ref metrics) => service.metrics(metrics.clone()),
+            None => service,
+        }
+    }
+
+    /// Bind to `addr` and return a future that accepts and serves connections on the event
+    /// loop referenced by `handle`.
+    ///
+    /// This is intended for embedding Shio into an application that already owns a tokio
+    /// `Core`. Unlike `Shio::run`, no threads are spawned. The returned future only completes
+    /// if accepting a connection fails.
+    ///
+    /// ```rust,no_run
+    /// # extern crate shio;
+    /// # extern crate tokio_core;
+    /// # use shio::prelude::*;
+    /// # use tokio_core::reactor::Core;
+    /// # fn main() {
+    /// let mut core = Core::new().unwrap();
+    /// let server = Shio::default()
+    ///     .route((Method::GET, "/", |_| Response::with("Hello World!\n")))
+    ///     .listen(":7878", &core.handle())
+    ///     .unwrap();
+    ///
+    /// core.run(server).unwrap();
+    /// # }
+    /// ```
+    pub fn listen<A: ToSocketAddrsExt>(
+        &self,
+        addr: A,
+        handle: &Handle,
+    ) -> Result<BoxFuture<(), ListenError>, ListenError> {
+        let service = self.service(handle);
+        let mut work = Vec::new();
+
+        for addr in addr.to_socket_addrs_ext()? {
+            let listener = bind(&addr, handle)?;
+            work.push(serve(listener, handle.clone(), service.clone(), Open::default()));
+        }
+
+        Ok(future::join_all(work).map(|_| ()).into_box())
+    }
+
     pub fn run<A: ToSocketAddrsExt>(&self, addr: A) -> Result<(), ListenError> {
+        self.run_with(addr, None::<future::Empty<(), ()>>)
+    }
+
+    /// Like `run`, but shuts down gracefully once `signal` completes.
+    ///
+    /// A graceful shutdown first fails readiness (see [`health`]) for the `drain_delay`, then
+    /// stops accepting connections and waits up to the `shutdown_timeout` for open ones to
+    /// close before returning. Idle keep-alive connections are closed right away, and busy ones
+    /// once their response is sent.
+    ///
+    /// ```rust,no_run
+    /// # extern crate futures;
+    /// # extern crate shio;
+    /// # use std::time::Duration;
+    /// # use futures::sync::oneshot;
+    /// # use futures::Future;
+    /// # use shio::prelude::*;
+    /// # fn main() {
+    /// let (shutdown, signal) = oneshot::channel::<()>();
+    /// // Call `shutdown.send(())` from a signal handler...
+    /// # drop(shutdown);
+    ///
+    /// Shio::default()
+    ///     .drain_delay(Duration::from_secs(5))
+    ///     .run_until(":7878", signal.map_err(|_| ()))
+    ///     .unwrap();
+    /// # }
+    /// ```
+    ///
+    /// [`health`]: health/index.html
+    pub fn run_until<A, F>(&self, addr: A, signal: F) -> Result<(), ListenError>
+    where
+        A: ToSocketAddrsExt,
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.run_with(addr, Some(signal))
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(use_debug, never_loop))]
+    fn run_with<A, F>(&self, addr: A, signal: Option<F>) -> Result<(), ListenError>
+    where
+        A: ToSocketAddrsExt,
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
         let addrs = addr.to_socket_addrs_ext()?.collect::<Vec<_>>();
         let mut children = Vec::new();
+        let stop = Arc::new(Mutex::new(Stop::default()));
+
+        if let Some(signal) = signal {
+            let stop = stop.clone();
+            let health = self.health.clone();
+            let drain_delay = self.drain_delay;
+
+            // Ends with the process if the signal never completes
+            thread::spawn(move || {
+                if signal.wait().is_ok() {
+                    health.drain();
+                    thread::sleep(drain_delay);
+                    stop.lock().unwrap().trigger();
+                }
+            });
+        }
 
         let spawn = || -> JoinHandle<Result<(), ListenError>> {
             let addrs = addrs.clone();
             let handler = self.handler.clone();
             let shared_state = self.shared_state.clone();
+            let local_state = self.local_state.clone();
+            let client = self.client.clone();
+            let metrics = self.metrics.clone();
+            let stopped = stop.lock().unwrap().register();
+            let shutdown_timeout = self.shutdown_timeout;
 
             thread::spawn(move || -> Result<(), ListenError> {
                 let mut core = Core::new()?;
                 let mut work = Vec::new();
                 let handle = core.handle();
-                let service = Service::new(handler, handle.clone(), shared_state);
+                let open = Open::default();
 
-                for addr in &addrs {
-                    let handle = handle.clone();
-                    let builder = (match *addr {
-                        SocketAddr::V4(_) => TcpBuilder::new_v4(),
-                        SocketAddr::V6(_) => TcpBuilder::new_v6(),
-                    })?;
+                // A dropped sender means the server is never stopped
+                let stopped = stopped
+                    .or_else(|_| future::empty::<(), ()>())
+                    .into_box()
+                    .shared();
 
-                    // Set SO_REUSEADDR on the socket
-                    builder.reuse_address(true)?;
+                let mut service = Service::new(handler, handle.clone(), shared_state)
+                    .local_state(build_local_state(&local_state, &client, &handle))
+                    .shutdown(stopped.clone());
 
-                    // Set SO_REUSEPORT on the socket (in unix)
-                    #[cfg(unix)]
-                    builder.reuse_port(true)?;
-
-                    builder.bind(&addr)?;
+                if let Some(metrics) = metrics {
+                    service = service.metrics(metrics);
+                }
 
-                    let listener = TcpListener::from_listener(
-                        // TODO: Should this be configurable somewhere?
-                        builder.listen(128)?,
-                        addr,
-                        &handle,
-                    )?;
+                for addr in &addrs {
+                    let listener = bind(addr, &handle)?;
+                    work.push(serve(listener, handle.clone(), service.clone(), open.clone()));
+                }
 
-                    let protocol = Http::new();
-                    let service = service.clone();
+                match core.run(future::join_all(work).select2(stopped)) {
+                    Ok(Either::A(_)) => return Ok(()),
+                    Err(Either::A((err, _))) => return Err(err),
+                    Ok(Either::B(_)) | Err(Either::B(_)) => {}
+                }
 
-                    let srv = listener.incoming().for_each(move |(socket, addr)| {
-                        protocol.bind_connection(&handle, socket, addr, service.clone());
+                // The listeners are closed; let open connections finish, which are closed
+                // as soon as they are idle
+                let deadline = Instant::now() + shutdown_timeout;
 
-                        Ok(())
-                    });
+                while open.count() > 0 {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
 
-                    work.push(srv);
+                    core.turn(Some(deadline - now));
                 }
 
-                core.run(future::join_all(work))?;
-
                 Ok(())
             })
         };
@@ -133,6 +386,10 @@ where
             };
 
             if respawn {
+                if let Some(ref metrics) = self.metrics {
+                    metrics.respawned();
+                }
+
                 children.push(spawn());
             }
         }
@@ -154,3 +411,189 @@ impl Shio<Router> {
         self
     }
 }
+
+fn build_local_state(
+    factories: &[Arc<LocalStateFactory>],
+    client: &client::Builder,
+    handle: &Handle,
+) -> TypeMap {
+    let mut local_state = TypeMap::new();
+    local_state.put::<client::Lazy>(client::Lazy::new(client.clone(), handle.clone()));
+
+    for factory in factories {
+        factory(handle, &mut local_state);
+    }
+
+    local_state
+}
+
+fn bind(addr: &SocketAddr, handle: &Handle) -> io::Result<TcpListener> {
+    let builder = (match *addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4(),
+        SocketAddr::V6(_) => TcpBuilder::new_v6(),
+    })?;
+
+    // Set SO_REUSEADDR on the socket
+    builder.reuse_address(true)?;
+
+    // Set SO_REUSEPORT on the socket (in unix)
+    #[cfg(unix)]
+    builder.reuse_port(true)?;
+
+    builder.bind(addr)?;
+
+    TcpListener::from_listener(
+        // TODO: Should this be configurable somewhere?
+        builder.listen(128)?,
+        addr,
+        handle,
+    )
+}
+
+fn serve<H: Handler + 'static>(
+    listener: TcpListener,
+    handle: Handle,
+    service: Service<H>,
+    open: Open,
+) -> BoxFuture<(), ListenError>
+where
+    <H::Result as IntoFuture>::Error: fmt::Debug + Send,
+{
+    listener
+        .incoming()
+        .for_each(move |(socket, addr)| {
+            let guard = open.guard();
+            let connection = service
+                .clone()
+                .remote_addr(addr)
+                .serve_connection(socket)
+                .map_err(|err| debug!("connection error: {}", err))
+                .then(move |result| {
+                    drop(guard);
+                    result
+                });
+
+            handle.spawn(connection);
+
+            Ok(())
+        })
+        .from_err()
+        .into_box()
+}
+
+/// Tells worker threads to stop accepting connections.
+#[derive(Default)]
+struct Stop {
+    stopping: bool,
+    workers: Vec<oneshot::Sender<()>>,
+}
+
+impl Stop {
+    /// Returns a future that completes once the server is stopping.
+    fn register(&mut self) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+
+        if self.stopping {
+            let _ = sender.send(());
+        } else {
+            self.workers.push(sender);
+        }
+
+        receiver
+    }
+
+    fn trigger(&mut self) {
+        self.stopping = true;
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.send(());
+        }
+    }
+}
+
+/// Counts the connections open on a worker thread.
+#[derive(Clone, Default)]
+struct Open(Rc<Cell<usize>>);
+
+impl Open {
+    fn count(&self) -> usize {
+        self.0.get()
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    fn guard(&self) -> OpenGuard {
+        self.0.set(self.0.get() + 1);
+        OpenGuard(self.0.clone())
+    }
+}
+
+struct OpenGuard(Rc<Cell<usize>>);
+
+impl Drop for OpenGuard {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::{future, Future};
+    use futures::sync::oneshot;
+
+    use super::Shio;
+
+    #[test]
+    fn test_run_until() {
+        let mut shio = Shio::default();
+        shio.threads(2);
+
+        // The signal has already completed, so the server stops right away
+        shio.run_until("127.0.0.1:0", future::ok(())).unwrap();
+
+        assert!(shio.health().is_draining());
+    }
+
+    #[test]
+    fn test_shutdown_closes_idle_connections() {
+        // Find a free port; each worker would bind a different one for port 0
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (shutdown, signal) = oneshot::channel::<()>();
+
+        let server = thread::spawn(move || {
+            let mut shio = Shio::default();
+            shio.threads(1);
+            shio.shutdown_timeout(Duration::from_secs(10));
+            shio.run_until(&*addr.to_string(), signal.map_err(|_| ())).unwrap();
+        });
+
+        let mut stream = loop {
+            match TcpStream::connect(addr) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        // Leave a keep-alive connection idle after one request
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0);
+            response.extend_from_slice(&buf[..n]);
+        }
+
+        let start = Instant::now();
+        shutdown.send(()).unwrap();
+        server.join().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,292 @@
+//! Server-Sent Events.
+//!
+//! An [`Sse`] responder streams [`Event`]s to the client in the `text/event-stream` format,
+//! sending a keep-alive comment whenever the stream has been idle for a while so that proxies
+//! do not close the connection.
+//!
+//! ```rust
+//! # extern crate futures;
+//! # extern crate shio;
+//! # use futures::stream;
+//! # use shio::prelude::*;
+//! # use shio::sse::{Event, Sse};
+//! fn updates(ctx: Context) -> Response {
+//!     // Resume after the last event the client saw, if it is reconnecting
+//!     let from = Sse::last_event_id(&ctx).and_then(|id| id.parse().ok()).unwrap_or(0);
+//!
+//!     let events = (from..from + 3).map(|id| {
+//!         Ok::<_, shio::Error>(Event::new(format!("update {}", id)).id(id.to_string()))
+//!     });
+//!
+//!     Response::with(Sse::new(&ctx, stream::iter_result(events)))
+//! }
+//! # fn main() { }
+//! ```
+//!
+//! [`Sse`]: struct.Sse.html
+//! [`Event`]: struct.Event.html
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Stream};
+use hyper::Chunk;
+use tokio_core::reactor::{Handle, Timeout};
+
+use context::Context;
+use http::header::{CacheControl, CacheDirective, ContentType, LastEventId};
+use response::{Body, Responder, Response};
+
+/// A single event in an event stream.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use shio::sse::Event;
+/// let event = Event::new("{\"cpu\": 0.42}")
+///     .event("stats")
+///     .id("42")
+///     .retry(Duration::from_secs(5));
+///
+/// assert_eq!(
+///     event.to_string(),
+///     "id: 42\nevent: stats\nretry: 5000\ndata: {\"cpu\": 0.42}\n\n"
+/// );
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Constructs a new `Event` with the given data.
+    ///
+    /// Data that spans multiple lines is sent as multiple `data` fields, which the client
+    /// joins back together.
+    pub fn new<D: Into<String>>(data: D) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the id of this event, which the client sends back in `Last-Event-ID` when it
+    /// reconnects.
+    pub fn id<S: Into<String>>(mut self, id: S) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the type of this event. Events without a type are dispatched as `message`.
+    pub fn event<S: Into<String>>(mut self, event: S) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets how long the client should wait before reconnecting if the connection is lost.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Line breaks would end the field early, so they are stripped from single-line fields
+        if let Some(ref id) = self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+
+        if let Some(retry) = self.retry {
+            let millis = retry.as_secs() * 1000 + u64::from(retry.subsec_nanos() / 1_000_000);
+            writeln!(f, "retry: {}", millis)?;
+        }
+
+        // Clients end a line at any of `\r\n`, `\r`, or `\n`
+        let data = self.data.replace("\r\n", "\n");
+        for line in data.split(|c| c == '\r' || c == '\n') {
+            writeln!(f, "data: {}", line)?;
+        }
+
+        writeln!(f)
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(|c| c == '\r' || c == '\n', "")
+}
+
+/// A responder that streams events to the client.
+///
+/// By default, a keep-alive comment is sent after 15 seconds without an event.
+pub struct Sse {
+    events: Box<Stream<Item = Event, Error = Box<StdError + Send + Sync>>>,
+    handle: Handle,
+    keep_alive: Option<Duration>,
+}
+
+impl Sse {
+    /// Constructs a new `Sse` that sends each event produced by `events`.
+    ///
+    /// If `events` fails, the connection is closed and the client will reconnect.
+    pub fn new<S>(ctx: &Context, events: S) -> Self
+    where
+        S: Stream<Item = Event> + 'static,
+        S::Error: Into<Box<StdError + Send + Sync>>,
+    {
+        Self {
+            events: Box::new(events.map_err(Into::into)),
+            handle: ctx.handle().clone(),
+            keep_alive: Some(Duration::from_secs(15)),
+        }
+    }
+
+    /// Sets how long the stream may be idle before a keep-alive comment is sent.
+    ///
+    /// `None` disables keep-alive comments.
+    pub fn keep_alive(mut self, keep_alive: Option<Duration>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Returns the id of the last event received by a reconnecting client.
+    pub fn last_event_id(ctx: &Context) -> Option<&str> {
+        ctx.headers()
+            .get::<LastEventId>()
+            .map(|last_event_id| &*last_event_id.0)
+    }
+}
+
+impl Responder for Sse {
+    type Result = Response;
+
+    fn to_response(self) -> Self::Result {
+        let keep_alive = self.keep_alive.and_then(|interval| {
+            match Timeout::new(interval, &self.handle) {
+                Ok(timeout) => Some((interval, timeout)),
+                Err(err) => {
+                    warn!("failed to start event stream keep-alive: {}", err);
+                    None
+                }
+            }
+        });
+
+        let stream = EventStream {
+            events: self.events,
+            keep_alive,
+        };
+
+        Response::build()
+            .header(ContentType("text/event-stream".parse().unwrap()))
+            .header(CacheControl(vec![CacheDirective::NoCache]))
+            .body(Body::wrap_stream(stream))
+    }
+}
+
+struct EventStream {
+    events: Box<Stream<Item = Event, Error = Box<StdError + Send + Sync>>>,
+    keep_alive: Option<(Duration, Timeout)>,
+}
+
+impl Stream for EventStream {
+    type Item = Chunk;
+    type Error = Box<StdError + Send + Sync>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.events.poll()? {
+            Async::Ready(Some(event)) => {
+                if let Some((interval, ref mut timeout)) = self.keep_alive {
+                    timeout.reset(Instant::now() + interval);
+                }
+
+                return Ok(Async::Ready(Some(event.to_string().into())));
+            }
+
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => {}
+        }
+
+        if let Some((interval, ref mut timeout)) = self.keep_alive {
+            if timeout.poll()?.is_ready() {
+                timeout.reset(Instant::now() + interval);
+
+                return Ok(Async::Ready(Some(":\n\n".into())));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{stream, Future, Stream};
+    use futures::sync::mpsc;
+    use tokio_core::reactor::Timeout;
+
+    use {Context, Response};
+    use http::header::{ContentType, LastEventId};
+    use test::TestClient;
+    use super::{Event, Sse};
+
+    #[test]
+    fn test_event_multiline_data() {
+        let event = Event::new("one\ntwo\r\nthree").id("1\n2");
+
+        assert_eq!(event.to_string(), "id: 12\ndata: one\ndata: two\ndata: three\n\n");
+    }
+
+    #[test]
+    fn test_event_lone_carriage_return() {
+        // A lone `\r` must not start a field of its own
+        let event = Event::new("a\revent: admin\r\rb");
+
+        assert_eq!(event.to_string(), "data: a\ndata: event: admin\ndata: \ndata: b\n\n");
+    }
+
+    #[test]
+    fn test_sse_response() {
+        let client = TestClient::new(|ctx: Context| {
+            let from: u32 = Sse::last_event_id(&ctx).unwrap().parse().unwrap();
+            let events = (from + 1..from + 3)
+                .map(|id| Ok::<_, ::Error>(Event::new("tick").id(id.to_string())));
+
+            Response::with(Sse::new(&ctx, stream::iter_result(events)))
+        });
+
+        client
+            .get("/")
+            .header(LastEventId("4".into()))
+            .send()
+            .assert_header(&ContentType("text/event-stream".parse().unwrap()))
+            .assert_body("id: 5\ndata: tick\n\nid: 6\ndata: tick\n\n");
+    }
+
+    #[test]
+    fn test_sse_keep_alive() {
+        let client = TestClient::new(|ctx: Context| {
+            let (sender, receiver) = mpsc::unbounded::<Event>();
+            let events = receiver.map_err(|()| -> ::Error { unreachable!() });
+
+            // End the stream once the first keep-alive has had time to be sent
+            let timeout = Timeout::new(Duration::from_millis(50), ctx.handle()).unwrap();
+            ctx.handle().spawn(timeout.then(move |_| {
+                drop(sender);
+                Ok(())
+            }));
+
+            Response::with(Sse::new(&ctx, events).keep_alive(Some(Duration::from_millis(10))))
+        });
+
+        assert!(client.get("/").send().text().starts_with(":\n\n"));
+    }
+}
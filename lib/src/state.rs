@@ -1,9 +1,11 @@
+use std::rc::Rc;
 use std::sync::Arc;
 
 use unsafe_any::UnsafeAny;
 
 use util::typemap::TypeMap;
 pub use util::typemap::Key;
+pub use util::swap::Swap;
 
 pub struct State {
     /// State local to a specific request.
@@ -11,6 +13,9 @@ pub struct State {
 
     /// State shared across all requests.
     shared: Arc<TypeMap<UnsafeAny + Send + Sync>>,
+
+    /// State shared across all requests handled by the current worker thread.
+    local: Rc<TypeMap>,
 }
 
 impl Default for State {
@@ -18,15 +23,25 @@ impl Default for State {
         Self {
             request: TypeMap::new(),
             shared: Arc::new(TypeMap::custom()),
+            local: Rc::new(TypeMap::new()),
         }
     }
 }
 
 impl State {
-    pub(crate) fn new(shared: Arc<TypeMap<UnsafeAny + Send + Sync>>) -> Self {
+    pub(crate) fn new(shared: Arc<TypeMap<UnsafeAny + Send + Sync>>, local: Rc<TypeMap>) -> Self {
+        Self::from_parts(TypeMap::new(), shared, local)
+    }
+
+    pub(crate) fn from_parts(
+        request: TypeMap,
+        shared: Arc<TypeMap<UnsafeAny + Send + Sync>>,
+        local: Rc<TypeMap>,
+    ) -> Self {
         Self {
-            request: TypeMap::new(),
+            request,
             shared,
+            local,
         }
     }
 
@@ -57,6 +72,11 @@ impl State {
     pub fn shared(&self) -> &TypeMap<UnsafeAny + Send + Sync> {
         &*self.shared
     }
+
+    /// Gets a reference to the worker-local state.
+    pub fn local(&self) -> &TypeMap {
+        &*self.local
+    }
 }
 
 #[doc(hidden)]
@@ -126,13 +146,24 @@ mod tests {
         assert_eq!(state.get::<RcNumber>(), &rc_num);
     }
 
+    #[test]
+    fn test_state_local() {
+        let mut local = TypeMap::new();
+        local.put::<RcNumber>(Rc::new(42));
+
+        let state = State::new(Arc::new(TypeMap::custom()), Rc::new(local));
+
+        assert_eq!(**state.local().get::<RcNumber>(), 42);
+        assert!(state.try_get::<RcNumber>().is_none());
+    }
+
     #[test]
     #[cfg(feature = "nightly")]
     fn test_state_shared_fallback() {
         let mut shared = TypeMap::<UnsafeAny + Send + Sync>::custom();
         shared.insert::<Number>(7878);
 
-        let state = State::new(Arc::new(shared));
+        let state = State::new(Arc::new(shared), Rc::new(TypeMap::new()));
 
         assert_eq!(state.get::<Number>(), &7878);
     }
@@ -0,0 +1,352 @@
+//! An in-process client for testing handlers.
+//!
+//! A [`TestClient`] runs requests through a [`Handler`] on a local event loop, without
+//! opening any sockets. Errors and panics are turned into responses the same way they are
+//! when running under [`Shio`].
+//!
+//! ```rust
+//! # use shio::prelude::*;
+//! # use shio::test::TestClient;
+//! fn hello(ctx: Context) -> Response {
+//!     Response::with(format!("Hello, {}!", &ctx.get::<Parameters>()["name"]))
+//! }
+//!
+//! let mut router = shio::router::Router::new();
+//! router.add((Method::GET, "/{name}", hello));
+//!
+//! let client = TestClient::new(router);
+//!
+//! client
+//!     .get("/World")
+//!     .send()
+//!     .assert_status(StatusCode::Ok)
+//!     .assert_body("Hello, World!");
+//! ```
+//!
+//! [`TestClient`]: struct.TestClient.html
+//! [`Handler`]: ../trait.Handler.html
+//! [`Shio`]: ../struct.Shio.html
+
+use std::cell::RefCell;
+use std::fmt;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use futures::{Future, IntoFuture, Stream};
+use hyper;
+use tokio_core::reactor::{Core, Handle};
+use unsafe_any::UnsafeAny;
+
+use {Context, Handler, State};
+use client;
+use http::{Method, StatusCode};
+use http::header::{Header, Headers};
+use service;
+use util::typemap::{Key, TypeMap};
+
+/// A client that sends requests to a [`Handler`] on a local event loop.
+///
+/// [`Handler`]: ../trait.Handler.html
+pub struct TestClient<H: Handler + 'static>
+where
+    <H::Result as IntoFuture>::Error: fmt::Debug + Send,
+{
+    handler: Arc<H>,
+    core: RefCell<Core>,
+    shared_state: Arc<TypeMap<UnsafeAny + Send + Sync>>,
+    local_state: Rc<TypeMap>,
+}
+
+impl<H: Handler + 'static> TestClient<H>
+where
+    <H::Result as IntoFuture>::Error: fmt::Debug + Send,
+{
+    /// Constructs a new `TestClient` for `handler`.
+    ///
+    /// # Panics
+    ///
+    /// If the event loop cannot be created.
+    pub fn new(handler: H) -> Self {
+        let core = Core::new().expect("failed to create event loop");
+        let mut local_state = TypeMap::new();
+        local_state.put::<client::Lazy>(client::Lazy::new(client::Builder::new(), core.handle()));
+
+        Self {
+            handler: Arc::new(handler),
+            core: RefCell::new(core),
+            shared_state: Arc::new(TypeMap::custom()),
+            local_state: Rc::new(local_state),
+        }
+    }
+
+    /// Add data to global state.
+    ///
+    /// # Panics
+    ///
+    /// If called after a request has been sent.
+    pub fn manage<K: Key>(mut self, value: K::Value) -> Self
+    where
+        <K as Key>::Value: Send + Sync,
+    {
+        Arc::get_mut(&mut self.shared_state)
+            .expect("state must be managed before sending requests")
+            .put::<K>(value);
+
+        self
+    }
+
+    /// Add data to worker-local state, created by `factory` with the `Handle` of the local
+    /// event loop.
+    ///
+    /// # Panics
+    ///
+    /// If called after a request has been sent.
+    pub fn manage_local<K, F>(mut self, factory: F) -> Self
+    where
+        K: Key,
+        F: FnOnce(&Handle) -> K::Value,
+    {
+        let value = factory(&self.handle());
+
+        Rc::get_mut(&mut self.local_state)
+            .expect("state must be managed before sending requests")
+            .put::<K>(value);
+
+        self
+    }
+
+    /// Returns a handle to the local event loop.
+    pub fn handle(&self) -> Handle {
+        self.core.borrow().handle()
+    }
+
+    /// Start building a request with the given method and URI.
+    ///
+    /// # Panics
+    ///
+    /// If `uri` is not a valid URI.
+    pub fn request<'a>(&'a self, method: Method, uri: &str) -> TestRequest<'a, H> {
+        let uri = uri.parse()
+            .unwrap_or_else(|err| panic!("invalid uri {:?}: {}", uri, err));
+
+        TestRequest {
+            client: self,
+            inner: hyper::Request::new(method.to_hyper_method(), uri),
+            remote_addr: None,
+        }
+    }
+
+    /// Start building a `GET` request.
+    pub fn get<'a>(&'a self, uri: &str) -> TestRequest<'a, H> {
+        self.request(Method::GET, uri)
+    }
+
+    /// Start building a `POST` request.
+    pub fn post<'a>(&'a self, uri: &str) -> TestRequest<'a, H> {
+        self.request(Method::POST, uri)
+    }
+
+    /// Start building a `PUT` request.
+    pub fn put<'a>(&'a self, uri: &str) -> TestRequest<'a, H> {
+        self.request(Method::PUT, uri)
+    }
+
+    /// Start building a `PATCH` request.
+    pub fn patch<'a>(&'a self, uri: &str) -> TestRequest<'a, H> {
+        self.request(Method::PATCH, uri)
+    }
+
+    /// Start building a `DELETE` request.
+    pub fn delete<'a>(&'a self, uri: &str) -> TestRequest<'a, H> {
+        self.request(Method::DELETE, uri)
+    }
+
+    fn send(&self, request: hyper::Request, remote_addr: Option<SocketAddr>) -> TestResponse {
+        let mut core = self.core.borrow_mut();
+        let (mut request, data) = service::from_hyper_request(request);
+        request.set_remote_addr(remote_addr);
+
+        let state = State::new(self.shared_state.clone(), self.local_state.clone());
+        let ctx = Context::new(core.handle(), request, state, data);
+
+        let work = service::dispatch(self.handler.clone(), ctx).and_then(|response| {
+            let status = response.status();
+            let headers = response.headers().clone();
+
+            response
+                .body()
+                .concat2()
+                .map(move |body| TestResponse {
+                    status,
+                    headers,
+                    body: body.to_vec(),
+                })
+        });
+
+        core.run(work)
+            .unwrap_or_else(|err| panic!("failed to read response body: {}", err))
+    }
+}
+
+/// A request being built by a [`TestClient`].
+///
+/// [`TestClient`]: struct.TestClient.html
+pub struct TestRequest<'a, H: Handler + 'static>
+where
+    <H::Result as IntoFuture>::Error: fmt::Debug + Send,
+{
+    client: &'a TestClient<H>,
+    inner: hyper::Request,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl<'a, H: Handler + 'static> TestRequest<'a, H>
+where
+    <H::Result as IntoFuture>::Error: fmt::Debug + Send,
+{
+    /// Set a header on the request.
+    pub fn header<T: Header>(mut self, header: T) -> Self {
+        self.inner.headers_mut().set(header);
+        self
+    }
+
+    /// Set the address the request appears to come from.
+    pub fn remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+
+    /// Set the body of the request.
+    pub fn body<B: Into<hyper::Body>>(mut self, body: B) -> Self {
+        self.inner.set_body(body);
+        self
+    }
+
+    /// Send the request to the handler and wait for the full response.
+    ///
+    /// # Panics
+    ///
+    /// If the response body fails to stream.
+    pub fn send(self) -> TestResponse {
+        self.client.send(self.inner, self.remote_addr)
+    }
+}
+
+/// A response received by a [`TestClient`], with its body read to completion.
+///
+/// [`TestClient`]: struct.TestClient.html
+#[derive(Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Get the status.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Get a reference to the headers.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Get a header by type.
+    pub fn header<T: Header>(&self) -> Option<&T> {
+        self.headers.get::<T>()
+    }
+
+    /// Get the body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Get the body as text.
+    ///
+    /// # Panics
+    ///
+    /// If the body is not valid UTF-8.
+    pub fn text(&self) -> &str {
+        ::std::str::from_utf8(&self.body).expect("response body is not valid UTF-8")
+    }
+
+    /// Assert that the response has the given status.
+    pub fn assert_status(&self, expected: StatusCode) -> &Self {
+        assert_eq!(self.status, expected, "unexpected response status");
+        self
+    }
+
+    /// Assert that the response has the given header.
+    pub fn assert_header<T: Header + PartialEq + fmt::Debug>(&self, expected: &T) -> &Self {
+        assert_eq!(self.header::<T>(), Some(expected), "unexpected response header");
+        self
+    }
+
+    /// Assert that the response body is the given text.
+    pub fn assert_body(&self, expected: &str) -> &Self {
+        assert_eq!(self.text(), expected, "unexpected response body");
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+
+    use {Context, Response};
+    use context::Key;
+    use ext::{BoxFuture, FutureExt};
+    use http::{Method, StatusCode};
+    use http::header::ContentType;
+    use super::TestClient;
+
+    struct Greeting;
+
+    impl Key for Greeting {
+        type Value = String;
+    }
+
+    #[test]
+    fn test_shared_state() {
+        let client = TestClient::new(|ctx: Context| {
+            Response::with(ctx.shared().get::<Greeting>().clone())
+        }).manage::<Greeting>("Hello".into());
+
+        client.get("/").send().assert_body("Hello");
+    }
+
+    #[test]
+    fn test_request_body_and_headers() {
+        let client = TestClient::new(|ctx: Context| -> BoxFuture<Response, ::Error> {
+            let json = ctx.headers().get::<ContentType>() == Some(&ContentType::json());
+            let method = ctx.method() == &::hyper::Method::Post;
+
+            ctx.data()
+                .concat2()
+                .map(move |body| {
+                    assert!(json && method);
+                    Response::build().header(ContentType::json()).body(body.to_vec())
+                })
+                .into_box()
+        });
+
+        client
+            .request(Method::POST, "/echo")
+            .header(ContentType::json())
+            .body("{}")
+            .send()
+            .assert_status(StatusCode::Ok)
+            .assert_header(&ContentType::json())
+            .assert_body("{}");
+    }
+
+    #[test]
+    fn test_panic_is_internal_server_error() {
+        let client = TestClient::new(|_: Context| -> Response { panic!("oh no") });
+
+        client.get("/").send().assert_status(StatusCode::InternalServerError);
+    }
+}
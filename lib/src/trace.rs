@@ -0,0 +1,539 @@
+//! W3C Trace Context propagation and request spans.
+//!
+//! The [`Tracing`] middleware continues the trace described by the `traceparent` and
+//! `tracestate` headers of a request, or starts a new trace, and gives the request a span
+//! of its own. The [`TraceContext`] of the request is put into the request state, and the
+//! client returned by `Context::client` propagates it to outbound requests, with the
+//! request's span as their parent.
+//!
+//! Once a response is ready, its span is exported to a [`Sink`] if the trace is sampled.
+//!
+//! ```rust,no_run
+//! # use shio::prelude::*;
+//! # use shio::middleware::Stack;
+//! # use shio::trace::{MemorySink, Tracing};
+//! # let router = shio::router::Router::new();
+//! let sink = MemorySink::new();
+//!
+//! Shio::new(Stack::new(router).with(Tracing::new(sink.clone())))
+//!     .run(":7878")
+//!     .unwrap();
+//! ```
+//!
+//! [`Tracing`]: struct.Tracing.html
+//! [`TraceContext`]: struct.TraceContext.html
+//! [`Sink`]: trait.Sink.html
+
+use std::fmt;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::Future;
+use hyper;
+
+use context::Context;
+use ext::{BoxFuture, FutureExt};
+use http::StatusCode;
+use http::header::{Formatter, Header, Raw};
+use middleware::{Middleware, Next};
+use response::Response;
+use router::RouteSlot;
+use state::Key;
+use util::random;
+
+// The most list members a `tracestate` header may carry
+const MAX_TRACESTATE_MEMBERS: usize = 32;
+
+/// The 16-byte ID of a trace, shared by every span in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TraceId(pub u128);
+
+impl TraceId {
+    /// Generates a new, random `TraceId`.
+    pub fn generate() -> Self {
+        TraceId(u128::from(random::u64()) << 64 | u128::from(random::u64()))
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// The 8-byte ID of a span.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpanId(pub u64);
+
+impl SpanId {
+    /// Generates a new, random `SpanId`.
+    pub fn generate() -> Self {
+        // Zero is not a valid ID
+        SpanId(random::u64().max(1))
+    }
+}
+
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// The `traceparent` header, naming a trace and the span a request was sent from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Traceparent {
+    /// The trace the request belongs to.
+    pub trace_id: TraceId,
+
+    /// The span that sent the request.
+    pub parent_id: SpanId,
+
+    /// The trace flags; only the lowest bit, `sampled`, is defined.
+    pub flags: u8,
+}
+
+impl Traceparent {
+    /// Whether the sender may have recorded its span.
+    pub fn sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.trim().split('-');
+
+        let version = fields.next()?;
+        if !is_hex(version, 2) || version == "ff" {
+            return None;
+        }
+
+        let trace_id = fields.next()?;
+        let parent_id = fields.next()?;
+        let flags = fields.next()?;
+
+        if !is_hex(trace_id, 32) || !is_hex(parent_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+
+        // Later versions may add fields, but version 00 has exactly four
+        if version == "00" && fields.next().is_some() {
+            return None;
+        }
+
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let parent_id = u64::from_str_radix(parent_id, 16).ok()?;
+
+        if trace_id == 0 || parent_id == 0 {
+            return None;
+        }
+
+        Some(Traceparent {
+            trace_id: TraceId(trace_id),
+            parent_id: SpanId(parent_id),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        })
+    }
+}
+
+impl fmt::Display for Traceparent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "00-{}-{}-{:02x}", self.trace_id, self.parent_id, self.flags)
+    }
+}
+
+impl Header for Traceparent {
+    fn header_name() -> &'static str {
+        "traceparent"
+    }
+
+    fn parse_header(raw: &Raw) -> hyper::Result<Self> {
+        raw.one()
+            .and_then(|line| str::from_utf8(line).ok())
+            .and_then(Traceparent::parse)
+            .ok_or(hyper::Error::Header)
+    }
+
+    fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+        f.fmt_line(self)
+    }
+}
+
+/// The `tracestate` header, carrying vendor-specific data along with a trace.
+///
+/// The list members are kept as they were received. A header with more than 32 members, or
+/// with a member that is not a `key=value` pair, fails to parse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tracestate(String);
+
+impl Tracestate {
+    /// Returns the header value.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Tracestate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Header for Tracestate {
+    fn header_name() -> &'static str {
+        "tracestate"
+    }
+
+    fn parse_header(raw: &Raw) -> hyper::Result<Self> {
+        let mut members = Vec::new();
+
+        // Multiple header lines combine into a single list
+        for line in raw {
+            let line = str::from_utf8(line).map_err(|_| hyper::Error::Header)?;
+
+            for member in line.split(',').map(str::trim).filter(|member| !member.is_empty()) {
+                let is_valid = member
+                    .find('=')
+                    .map_or(false, |index| index > 0 && index < member.len() - 1);
+
+                if !is_valid {
+                    return Err(hyper::Error::Header);
+                }
+
+                members.push(member);
+            }
+        }
+
+        if members.is_empty() || members.len() > MAX_TRACESTATE_MEMBERS {
+            return Err(hyper::Error::Header);
+        }
+
+        Ok(Tracestate(members.join(",")))
+    }
+
+    fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+        f.fmt_line(&self.0)
+    }
+}
+
+/// The trace a request belongs to, and the span of this service handling it.
+///
+/// Put into the request state by [`Tracing`], where it may be read with
+/// `ctx.get::<TraceContext>()`.
+///
+/// [`Tracing`]: struct.Tracing.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    /// The trace the request belongs to.
+    pub trace_id: TraceId,
+
+    /// The span of this request.
+    pub span_id: SpanId,
+
+    /// The span that sent the request, if the request continued an existing trace.
+    pub parent_id: Option<SpanId>,
+
+    /// The trace flags.
+    pub flags: u8,
+
+    /// The vendor-specific state of the trace.
+    pub state: Option<Tracestate>,
+}
+
+impl TraceContext {
+    /// Starts a new, sampled trace.
+    pub fn new() -> Self {
+        Self {
+            trace_id: TraceId::generate(),
+            span_id: SpanId::generate(),
+            parent_id: None,
+            flags: 1,
+            state: None,
+        }
+    }
+
+    /// Continues the trace of `parent` with a new span.
+    pub fn child_of(parent: &Traceparent, state: Option<Tracestate>) -> Self {
+        Self {
+            trace_id: parent.trace_id,
+            span_id: SpanId::generate(),
+            parent_id: Some(parent.parent_id),
+            flags: parent.flags,
+            state,
+        }
+    }
+
+    /// Whether the span of this request is recorded.
+    pub fn sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+
+    /// The `traceparent` header for a request sent from the span of this request.
+    pub fn traceparent(&self) -> Traceparent {
+        Traceparent {
+            trace_id: self.trace_id,
+            parent_id: self.span_id,
+            flags: self.flags,
+        }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Key for TraceContext {
+    type Value = Self;
+}
+
+/// A finished span, recording the handling of one request.
+#[derive(Clone, Debug)]
+pub struct Span {
+    /// The trace the span belongs to.
+    pub trace_id: TraceId,
+
+    /// The ID of this span.
+    pub span_id: SpanId,
+
+    /// The span that sent the request, if any.
+    pub parent_id: Option<SpanId>,
+
+    /// The method and route pattern of the request, such as `GET /user/{id}`, or only the
+    /// method if no route matched.
+    pub name: String,
+
+    /// When the request began to be handled.
+    pub start: SystemTime,
+
+    /// How long it took to produce a response.
+    pub duration: Duration,
+
+    /// The status of the response.
+    pub status: StatusCode,
+}
+
+/// A destination for finished spans, such as a tracing backend.
+///
+/// `export` is called on the worker thread that handled the request, so it should queue
+/// the span rather than block on sending it.
+pub trait Sink: Send + Sync {
+    /// Export a finished span.
+    fn export(&self, span: Span);
+}
+
+impl<S: Sink + ?Sized> Sink for Arc<S> {
+    fn export(&self, span: Span) {
+        (**self).export(span)
+    }
+}
+
+/// A [`Sink`] that keeps spans in memory, for tests.
+///
+/// Clones share the same spans.
+///
+/// [`Sink`]: trait.Sink.html
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    spans: Arc<Mutex<Vec<Span>>>,
+}
+
+impl MemorySink {
+    /// Constructs a new, empty `MemorySink`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the spans exported so far.
+    pub fn spans(&self) -> Vec<Span> {
+        self.spans.lock().unwrap().clone()
+    }
+}
+
+impl Sink for MemorySink {
+    fn export(&self, span: Span) {
+        self.spans.lock().unwrap().push(span);
+    }
+}
+
+/// [`Middleware`] that gives every request a span in a W3C trace.
+///
+/// See the [module documentation](index.html) for details.
+///
+/// [`Middleware`]: ../middleware/trait.Middleware.html
+#[derive(Clone)]
+pub struct Tracing {
+    sink: Arc<Sink>,
+}
+
+impl Tracing {
+    /// Constructs a new `Tracing` that exports spans to `sink`.
+    pub fn new<S: Sink + 'static>(sink: S) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+impl fmt::Debug for Tracing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracing").finish()
+    }
+}
+
+impl Middleware for Tracing {
+    fn call(&self, mut ctx: Context, next: Next) -> BoxFuture<Response, hyper::Error> {
+        let trace = match ctx.headers().get::<Traceparent>() {
+            Some(parent) => TraceContext::child_of(parent, ctx.headers().get().cloned()),
+            None => TraceContext::new(),
+        };
+
+        let start = SystemTime::now();
+        let timer = Instant::now();
+        let method = ctx.method().to_string();
+
+        let slot = RouteSlot::install(&mut ctx);
+        ctx.put::<TraceContext>(trace.clone());
+
+        let sink = self.sink.clone();
+
+        next.call(ctx)
+            .map(move |response| {
+                if trace.sampled() {
+                    sink.export(Span {
+                        trace_id: trace.trace_id,
+                        span_id: trace.span_id,
+                        parent_id: trace.parent_id,
+                        name: match slot.get() {
+                            Some(route) => format!("{} {}", method, route),
+                            None => method,
+                        },
+                        start,
+                        duration: timer.elapsed(),
+                        status: response.status(),
+                    });
+                }
+
+                response
+            })
+            .into_box()
+    }
+}
+
+/// Whether `value` is `len` lowercase hexadecimal digits.
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.bytes().all(|b| b"0123456789abcdef".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use {Context, Response};
+    use http::{Method, StatusCode};
+    use metrics::Metrics;
+    use middleware::Stack;
+    use router::Router;
+    use test::TestClient;
+    use super::{MemorySink, SpanId, TraceContext, TraceId, Traceparent, Tracestate, Tracing};
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let parent = Traceparent::parse(PARENT).unwrap();
+        assert_eq!(parent.trace_id, TraceId(0x4bf92f3577b34da6a3ce929d0e0e4736));
+        assert_eq!(parent.parent_id, SpanId(0x00f067aa0ba902b7));
+        assert!(parent.sampled());
+        assert_eq!(parent.to_string(), PARENT);
+
+        // Unknown versions may carry more fields
+        assert!(Traceparent::parse(&format!("cc{}-extra", &PARENT[2..])).is_some());
+
+        for invalid in &[
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert!(Traceparent::parse(invalid).is_none(), "parsed {:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_tracing() {
+        let sink = MemorySink::new();
+
+        let mut router = Router::new();
+        router.add((Method::GET, "/user/{id}", |ctx: Context| {
+            Response::with(ctx.get::<TraceContext>().traceparent().to_string())
+        }));
+
+        let client = TestClient::new(Stack::new(router).with(Tracing::new(sink.clone())));
+
+        let parent = Traceparent::parse(PARENT).unwrap();
+        let response = client
+            .get("/user/1")
+            .header(parent)
+            .header(Tracestate("vendor=value".into()))
+            .send();
+
+        let spans = sink.spans();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].trace_id, parent.trace_id);
+        assert_eq!(spans[0].parent_id, Some(parent.parent_id));
+        assert_eq!(spans[0].name, "GET /user/{id}");
+        assert_eq!(spans[0].status, StatusCode::Ok);
+
+        // Outbound requests are sent from the span of this request
+        let outbound = Traceparent::parse(response.text()).unwrap();
+        assert_eq!(outbound.trace_id, parent.trace_id);
+        assert_eq!(outbound.parent_id, spans[0].span_id);
+
+        // A new trace is started without a traceparent
+        client.get("/missing").send();
+
+        let spans = sink.spans();
+        assert_eq!(spans.len(), 2);
+        assert_ne!(spans[1].trace_id, parent.trace_id);
+        assert_eq!(spans[1].parent_id, None);
+        assert_eq!(spans[1].name, "GET");
+    }
+
+    #[test]
+    fn test_with_metrics() {
+        let sink = MemorySink::new();
+        let metrics = Metrics::new();
+
+        let mut router = Router::new();
+        router.add((Method::GET, "/user/{id}", |_: Context| Response::new()));
+
+        // Both share the slot the router records the matched pattern in
+        let client = TestClient::new(
+            Stack::new(router)
+                .with(metrics.clone())
+                .with(Tracing::new(sink.clone())),
+        );
+
+        client.get("/user/1").send();
+
+        assert_eq!(sink.spans()[0].name, "GET /user/{id}");
+        assert!(metrics.render().contains(
+            "shio_http_requests_total{method=\"GET\",route=\"/user/{id}\",status=\"200\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn test_unsampled() {
+        let sink = MemorySink::new();
+        let client = TestClient::new(
+            Stack::new(|_: Context| Response::new()).with(Tracing::new(sink.clone())),
+        );
+
+        let mut parent = Traceparent::parse(PARENT).unwrap();
+        parent.flags = 0;
+
+        client.get("/").header(parent).send();
+        assert!(sink.spans().is_empty());
+    }
+}
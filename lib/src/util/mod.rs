@@ -1,3 +1,7 @@
 
 
 pub mod typemap;
+pub mod swap;
+pub(crate) mod json;
+pub(crate) mod percent;
+pub(crate) mod random;
@@ -0,0 +1,59 @@
+//! Decoding of `%XX` escapes, in request paths and in `application/x-www-form-urlencoded`
+//! forms.
+
+/// Decodes the `%XX` escapes in `input`, returning `None` if an escape is malformed or the
+/// result is not UTF-8.
+pub(crate) fn decode(input: &str) -> Option<String> {
+    decode_with(input, false)
+}
+
+/// Decodes a name or value of a form, where a `+` also stands for a space.
+pub(crate) fn decode_form(input: &str) -> Option<String> {
+    decode_with(input, true)
+}
+
+fn decode_with(input: &str, plus_as_space: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut iter = input.bytes();
+
+    while let Some(b) = iter.next() {
+        match b {
+            b'%' => {
+                let high = hex_digit(iter.next()?)?;
+                let low = hex_digit(iter.next()?)?;
+
+                bytes.push(high << 4 | low);
+            }
+
+            b'+' if plus_as_space => bytes.push(b' '),
+            _ => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_form};
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("a%20b/%E2%9C%93").unwrap(), "a b/\u{2713}");
+        assert_eq!(decode("a+b").unwrap(), "a+b");
+        assert_eq!(decode("%2"), None);
+        assert_eq!(decode("%zz"), None);
+        assert_eq!(decode("%+1"), None);
+        assert_eq!(decode("%ff"), None);
+    }
+
+    #[test]
+    fn test_decode_form() {
+        assert_eq!(decode_form("a+b%2Bc").unwrap(), "a b+c");
+        assert_eq!(decode_form("%-1"), None);
+    }
+}
@@ -0,0 +1,35 @@
+//! Random numbers for identifiers, such as request and trace IDs, and for secrets.
+//!
+//! `u64` is unpredictable enough that IDs can't be guessed from one another, but is not
+//! suitable for secrets; `fill_secret` reads from the operating system for those.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::{OsRng, Rng};
+
+/// Returns a random `u64`.
+pub(crate) fn u64() -> u64 {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        // Randomly keyed per thread; hashing a counter gives a different value every call
+        static KEYS: RandomState = RandomState::new();
+    }
+
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    KEYS.with(|keys| {
+        let mut hasher = keys.build_hasher();
+        counter.hash(&mut hasher);
+        hasher.finish()
+    })
+}
+
+/// Fills `bytes` with random bytes from the operating system.
+pub(crate) fn fill_secret(bytes: &mut [u8]) {
+    OsRng::new()
+        .expect("the random number generator of the operating system is unavailable")
+        .fill_bytes(bytes);
+}
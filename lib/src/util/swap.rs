@@ -0,0 +1,113 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// A shared value that may be atomically replaced while the server is running.
+///
+/// Readers take a cheap, consistent snapshot with `Swap::load`; the snapshot is unaffected by
+/// later calls to `Swap::store`. Cloning a `Swap` results in a handle to the same value so
+/// one may be kept outside of Shio (e.g., in a signal handler) to publish updates.
+///
+/// ```rust
+/// # use shio::prelude::*;
+/// # use shio::context::Key;
+/// # use shio::state::Swap;
+/// struct Config;
+///
+/// impl Key for Config {
+///     type Value = Swap<String>;
+/// }
+///
+/// let config = Swap::new(String::from("v1"));
+///
+/// Shio::default()
+///     .manage::<Config>(config.clone())
+///     .route((Method::GET, "/", |ctx: Context| {
+///         Response::with(format!("{}\n", ctx.shared().get::<Config>().load()))
+///     }));
+///
+/// // Later, from anywhere that holds a clone
+/// config.store(String::from("v2"));
+/// ```
+pub struct Swap<T> {
+    value: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Swap<T> {
+    /// Constructs a new `Swap` holding `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            value: Arc::new(RwLock::new(Arc::new(value))),
+        }
+    }
+
+    /// Returns a snapshot of the current value.
+    pub fn load(&self) -> Arc<T> {
+        match self.value.read() {
+            Ok(value) => value.clone(),
+            // The lock is only held to clone or replace an `Arc`, which leaves the value
+            // consistent even if that panicked
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replaces the current value, returning the previous one.
+    pub fn store(&self, value: T) -> Arc<T> {
+        let value = Arc::new(value);
+
+        match self.value.write() {
+            Ok(mut current) => ::std::mem::replace(&mut *current, value),
+            Err(poisoned) => ::std::mem::replace(&mut *poisoned.into_inner(), value),
+        }
+    }
+}
+
+impl<T> Clone for Swap<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<T: Default> Default for Swap<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Swap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Swap").field(&self.load()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Swap;
+
+    #[test]
+    fn test_store_and_load() {
+        let swap = Swap::new(1);
+        assert_eq!(*swap.store(2), 1);
+        assert_eq!(*swap.load(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_is_consistent() {
+        let swap = Swap::new(String::from("old"));
+        let snapshot = swap.load();
+        swap.store(String::from("new"));
+
+        assert_eq!(*snapshot, "old");
+        assert_eq!(*swap.load(), "new");
+    }
+
+    #[test]
+    fn test_clone_shares_value() {
+        let swap = Swap::new(1);
+        let other = swap.clone();
+        other.store(2);
+
+        assert_eq!(*swap.load(), 2);
+    }
+}
@@ -0,0 +1,723 @@
+//! WebSocket connections.
+//!
+//! A [`WebSocket`] is a [`Handler`] that validates the opening handshake, answers with
+//! `101 Switching Protocols`, and then hands the connection to a function as a [`Socket`],
+//! a `Stream` and `Sink` of [`Message`]s running on the event loop of the worker thread.
+//!
+//! Pings are answered automatically and the closing handshake is completed when the client
+//! closes the connection, which ends the stream.
+//!
+//! ```rust
+//! # use shio::prelude::*;
+//! # use shio::websocket::{Socket, WebSocket};
+//! let echo = WebSocket::new(|_: Context, socket: Socket| {
+//!     let (sink, stream) = socket.split();
+//!
+//!     stream.forward(sink).map(|_| ())
+//! });
+//!
+//! Shio::default().route((Method::GET, "/echo", echo));
+//! ```
+//!
+//! [`WebSocket`]: struct.WebSocket.html
+//! [`Handler`]: ../trait.Handler.html
+//! [`Socket`]: struct.Socket.html
+//! [`Message`]: enum.Message.html
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::str;
+use std::sync::Arc;
+
+use base64;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use hyper::Method;
+use sha1::Sha1;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use context::Context;
+use handler::Handler;
+use http::StatusCode;
+use http::header::{Allow, Headers};
+use response::Response;
+use service::Io;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Writes are buffered up to this size before `Socket::start_send` waits for a flush
+const WRITE_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A message sent over a WebSocket connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+
+    /// A binary message.
+    Binary(Vec<u8>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl<'a> From<&'a str> for Message {
+    fn from(text: &'a str) -> Self {
+        Message::Text(text.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Self {
+        Message::Binary(bytes)
+    }
+}
+
+/// A [`Handler`] that accepts WebSocket connections.
+///
+/// Requests that are not a valid WebSocket handshake are answered with
+/// `400 Bad Request`, or `426 Upgrade Required` if the client asked for a protocol or
+/// version that is not supported. Requests with a method other than `GET` are answered with
+/// `405 Method Not Allowed`.
+///
+/// [`Handler`]: ../trait.Handler.html
+pub struct WebSocket<F> {
+    handler: Arc<F>,
+    max_message_size: usize,
+}
+
+impl<F, R> WebSocket<F>
+where
+    F: Fn(Context, Socket) -> R + Send + Sync + 'static,
+    R: IntoFuture<Item = ()> + 'static,
+    R::Error: fmt::Debug,
+{
+    /// Constructs a new `WebSocket` that calls `handler` with each accepted connection.
+    ///
+    /// The connection is closed when the future returned by `handler` completes and the
+    /// `Socket` has been dropped.
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            max_message_size: 1024 * 1024,
+        }
+    }
+
+    /// Sets the largest message that will be accepted from the client, in bytes.
+    ///
+    /// A client that sends a larger message is disconnected with close code `1009`.
+    /// Defaults to 1 MiB.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+}
+
+impl<F, R> Handler for WebSocket<F>
+where
+    F: Fn(Context, Socket) -> R + Send + Sync + 'static,
+    R: IntoFuture<Item = ()> + 'static,
+    R::Error: fmt::Debug,
+{
+    type Result = Response;
+
+    fn call(&self, ctx: Context) -> Self::Result {
+        if *ctx.method() != Method::Get {
+            return Response::build()
+                .status(StatusCode::MethodNotAllowed)
+                .header(Allow(vec![Method::Get]))
+                .into();
+        }
+
+        let accept = match handshake(ctx.headers()) {
+            Ok(accept) => accept,
+            Err(response) => return response,
+        };
+
+        let mut response: Response = Response::build()
+            .status(StatusCode::SwitchingProtocols)
+            .into();
+
+        {
+            let headers = response.headers_mut();
+            headers.set_raw("Upgrade", "websocket");
+            headers.set_raw("Connection", "Upgrade");
+            headers.set_raw("Sec-WebSocket-Accept", accept);
+        }
+
+        let handler = self.handler.clone();
+        let max_message_size = self.max_message_size;
+
+        response.set_upgrade(Box::new(move |io, read_buf| {
+            let handle = ctx.handle().clone();
+            let socket = Socket::new(io, read_buf, max_message_size);
+
+            handle.spawn(
+                handler(ctx, socket)
+                    .into_future()
+                    .map_err(|err| debug!("websocket error: {:?}", err)),
+            );
+        }));
+
+        response
+    }
+}
+
+/// Validates the opening handshake, returning the `Sec-WebSocket-Accept` value to send or
+/// the response that rejects the request.
+fn handshake(headers: &Headers) -> Result<String, Response> {
+    if !has_token(headers, "Upgrade", "websocket") {
+        return Err(upgrade_required("Upgrade", "websocket"));
+    }
+
+    if !has_token(headers, "Sec-WebSocket-Version", "13") {
+        return Err(upgrade_required("Sec-WebSocket-Version", "13"));
+    }
+
+    let key = headers
+        .get_raw("Sec-WebSocket-Key")
+        .and_then(|raw| raw.one())
+        .filter(|key| {
+            base64::decode(key)
+                .map(|nonce| nonce.len() == 16)
+                .unwrap_or(false)
+        });
+
+    match key {
+        Some(key) if has_token(headers, "Connection", "upgrade") => {
+            let mut sha1 = Sha1::new();
+            sha1.update(key);
+            sha1.update(GUID.as_bytes());
+
+            Ok(base64::encode(&sha1.digest().bytes()))
+        }
+
+        _ => Err(Response::build().status(StatusCode::BadRequest).into()),
+    }
+}
+
+fn upgrade_required(name: &'static str, value: &'static str) -> Response {
+    let mut response: Response = Response::build()
+        .status(StatusCode::UpgradeRequired)
+        .into();
+
+    response.headers_mut().set_raw(name, value);
+    response
+}
+
+/// Returns whether the comma-separated header `name` contains `token`, ignoring case.
+fn has_token(headers: &Headers, name: &str, token: &str) -> bool {
+    headers.get_raw(name).map_or(false, |raw| {
+        raw.iter().any(|line| {
+            String::from_utf8_lossy(line)
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        })
+    })
+}
+
+/// An accepted WebSocket connection.
+///
+/// A `Socket` is a `Stream` of the messages sent by the client, ending when the client
+/// closes the connection, and a `Sink` of messages to send. Use `Stream::split` to read
+/// and write from separate tasks.
+pub struct Socket {
+    io: Box<Io>,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    max_message_size: usize,
+    // A fragmented message being received, as its opcode and payload so far
+    partial: Option<(u8, Vec<u8>)>,
+    // Whether a close frame has been sent, after which nothing more may be sent
+    close_sent: bool,
+    // Whether the stream has ended, either by a close frame or an error
+    finished: bool,
+}
+
+impl Socket {
+    fn new(io: Box<Io>, read_buf: Bytes, max_message_size: usize) -> Self {
+        Self {
+            io,
+            read_buf: BytesMut::from(read_buf),
+            write_buf: BytesMut::new(),
+            max_message_size,
+            partial: None,
+            close_sent: false,
+            finished: false,
+        }
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) {
+        let len = payload.len();
+        self.write_buf.reserve(len + 10);
+        self.write_buf.put_u8(0x80 | opcode);
+
+        if len < 126 {
+            self.write_buf.put_u8(len as u8);
+        } else if len <= 0xFFFF {
+            self.write_buf.put_u8(126);
+            self.write_buf.put_u16_be(len as u16);
+        } else {
+            self.write_buf.put_u8(127);
+            self.write_buf.put_u64_be(len as u64);
+        }
+
+        self.write_buf.put_slice(payload);
+    }
+
+    fn write_close(&mut self, code: u16) {
+        if !self.close_sent {
+            self.close_sent = true;
+            self.write_frame(OP_CLOSE, &[(code >> 8) as u8, code as u8]);
+        }
+    }
+
+    fn poll_flush(&mut self) -> Poll<(), io::Error> {
+        while !self.write_buf.is_empty() {
+            let n = try_ready!(self.io.poll_write(&self.write_buf));
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+
+            self.write_buf.split_to(n);
+        }
+
+        self.io.poll_flush()
+    }
+
+    /// Ends the stream after a protocol violation, telling the client why.
+    fn fail(&mut self, err: ProtocolError) -> io::Error {
+        self.finished = true;
+        self.write_close(err.close_code());
+
+        // The close frame is sent on a best-effort basis; the connection is abandoned anyway
+        let _ = self.poll_flush();
+
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        let buf = &self.read_buf;
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+
+        let fin = buf[0] & 0x80 != 0;
+        let opcode = buf[0] & 0x0F;
+
+        if buf[0] & 0x70 != 0 {
+            return Err(ProtocolError::Protocol("reserved bits set"));
+        }
+
+        if buf[1] & 0x80 == 0 {
+            return Err(ProtocolError::Protocol("frame from client is not masked"));
+        }
+
+        let (len, offset) = match buf[1] & 0x7F {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => ((u64::from(buf[2]) << 8) | u64::from(buf[3]), 4),
+            127 if buf.len() < 10 => return Ok(None),
+            127 => (buf[2..10].iter().fold(0, |len, &b| (len << 8) | u64::from(b)), 10),
+            len => (u64::from(len), 2),
+        };
+
+        if opcode >= OP_CLOSE && (!fin || len > 125) {
+            return Err(ProtocolError::Protocol("invalid control frame"));
+        }
+
+        if len > self.max_message_size as u64 {
+            return Err(ProtocolError::TooBig);
+        }
+
+        let len = len as usize;
+        if buf.len() < offset + 4 + len {
+            let additional = offset + 4 + len - buf.len();
+            self.read_buf.reserve(additional);
+
+            return Ok(None);
+        }
+
+        let mut frame = self.read_buf.split_to(offset + 4 + len);
+        let payload = frame.split_off(offset + 4);
+        let mask = &frame[offset..];
+
+        let payload = payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect();
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    /// Handles a frame, returning a message once one is complete.
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, ProtocolError> {
+        let (opcode, payload) = match frame.opcode {
+            OP_CONTINUATION => {
+                let (opcode, mut payload) = self.partial
+                    .take()
+                    .ok_or(ProtocolError::Protocol("unexpected continuation frame"))?;
+
+                if payload.len() + frame.payload.len() > self.max_message_size {
+                    return Err(ProtocolError::TooBig);
+                }
+
+                payload.extend_from_slice(&frame.payload);
+                (opcode, payload)
+            }
+
+            OP_TEXT | OP_BINARY if self.partial.is_some() => {
+                return Err(ProtocolError::Protocol("expected continuation frame"));
+            }
+
+            OP_TEXT | OP_BINARY => (frame.opcode, frame.payload),
+
+            OP_CLOSE => {
+                // Echo the status code back to complete the closing handshake
+                let code = match frame.payload.len() {
+                    0 => CLOSE_NORMAL,
+                    1 => return Err(ProtocolError::Protocol("invalid close frame")),
+                    _ => (u16::from(frame.payload[0]) << 8) | u16::from(frame.payload[1]),
+                };
+
+                if !is_valid_close_code(code) {
+                    return Err(ProtocolError::Protocol("invalid close code"));
+                }
+
+                if frame.payload.len() > 2 && str::from_utf8(&frame.payload[2..]).is_err() {
+                    return Err(ProtocolError::InvalidUtf8);
+                }
+
+                self.write_close(code);
+                self.finished = true;
+
+                return Ok(None);
+            }
+
+            OP_PING => {
+                if !self.close_sent {
+                    self.write_frame(OP_PONG, &frame.payload);
+                }
+
+                return Ok(None);
+            }
+
+            OP_PONG => return Ok(None),
+
+            _ => return Err(ProtocolError::Protocol("unknown opcode")),
+        };
+
+        if !frame.fin {
+            self.partial = Some((opcode, payload));
+            return Ok(None);
+        }
+
+        if opcode == OP_TEXT {
+            String::from_utf8(payload)
+                .map(|text| Some(Message::Text(text)))
+                .map_err(|_| ProtocolError::InvalidUtf8)
+        } else {
+            Ok(Some(Message::Binary(payload)))
+        }
+    }
+}
+
+impl Stream for Socket {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Message>, io::Error> {
+        loop {
+            // Make progress on any pongs or close frames written in response to the client
+            let _ = self.poll_flush()?;
+
+            if self.finished {
+                return Ok(Async::Ready(None));
+            }
+
+            let frame = match self.read_frame() {
+                Ok(frame) => frame,
+                Err(err) => return Err(self.fail(err)),
+            };
+
+            if let Some(frame) = frame {
+                match self.on_frame(frame) {
+                    Ok(Some(message)) => return Ok(Async::Ready(Some(message))),
+                    Ok(None) => continue,
+                    Err(err) => return Err(self.fail(err)),
+                }
+            }
+
+            self.read_buf.reserve(4096);
+            if try_ready!(AsyncRead::read_buf(&mut self.io, &mut self.read_buf)) == 0 {
+                // The client went away without a closing handshake
+                self.finished = true;
+            }
+        }
+    }
+}
+
+impl Sink for Socket {
+    type SinkItem = Message;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, message: Message) -> StartSend<Message, io::Error> {
+        if self.close_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "websocket connection is closed",
+            ));
+        }
+
+        if self.write_buf.len() >= WRITE_BUFFER_SIZE && self.poll_flush()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(message));
+        }
+
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(bytes) => self.write_frame(OP_BINARY, &bytes),
+        }
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.poll_flush()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        self.write_close(CLOSE_NORMAL);
+        try_ready!(self.poll_flush());
+
+        self.io.shutdown()
+    }
+}
+
+impl fmt::Debug for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Socket").finish()
+    }
+}
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+const CLOSE_NORMAL: u16 = 1000;
+
+/// Whether a close frame may carry `code`; the others are reserved or only used locally.
+fn is_valid_close_code(code: u16) -> bool {
+    match code {
+        1000..=1003 | 1007..=1011 | 3000..=4999 => true,
+        _ => false,
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum ProtocolError {
+    Protocol(&'static str),
+    InvalidUtf8,
+    TooBig,
+}
+
+impl ProtocolError {
+    fn close_code(&self) -> u16 {
+        match *self {
+            ProtocolError::Protocol(_) => 1002,
+            ProtocolError::InvalidUtf8 => 1007,
+            ProtocolError::TooBig => 1009,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolError::Protocol(reason) => write!(f, "websocket protocol error: {}", reason),
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+impl StdError for ProtocolError {
+    fn description(&self) -> &str {
+        match *self {
+            ProtocolError::Protocol(reason) => reason,
+            ProtocolError::InvalidUtf8 => "websocket text message is not valid UTF-8",
+            ProtocolError::TooBig => "websocket message is too big",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{self, SocketAddr};
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use futures::{future, Future, Sink, Stream};
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::Core;
+
+    use Context;
+    use http::{Method, StatusCode};
+    use service::Service;
+    use test::TestClient;
+    use util::typemap::TypeMap;
+    use super::{Socket, WebSocket};
+
+    fn echo(max_message_size: usize) -> SocketAddr {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut core = Core::new().unwrap();
+            let handle = core.handle();
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let listener = TcpListener::from_listener(listener, &addr, &handle).unwrap();
+
+            let handler = WebSocket::new(|_: Context, socket: Socket| {
+                let (sink, stream) = socket.split();
+                stream.forward(sink).map(|_| ())
+            }).max_message_size(max_message_size);
+
+            let shared_state = Arc::new(TypeMap::custom());
+            let service = Service::new(Arc::new(handler), handle.clone(), shared_state);
+
+            core.handle().spawn(
+                listener
+                    .incoming()
+                    .for_each(move |(socket, _)| {
+                        handle.spawn(service.serve_connection(socket).map_err(|_| ()));
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+            );
+
+            tx.send(addr).unwrap();
+            core.run(future::empty::<(), ()>()).unwrap();
+        });
+
+        rx.recv().unwrap()
+    }
+
+    fn connect(addr: SocketAddr) -> net::TcpStream {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        ).unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        stream
+    }
+
+    fn send(stream: &mut net::TcpStream, opcode: u8, payload: &[u8]) {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x80 | opcode];
+
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.push((payload.len() >> 8) as u8);
+            frame.push(payload.len() as u8);
+        }
+
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).unwrap();
+    }
+
+    fn recv(stream: &mut net::TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head).unwrap();
+
+        let mut payload = vec![0; usize::from(head[1])];
+        stream.read_exact(&mut payload).unwrap();
+
+        (head[0], payload)
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut stream = connect(echo(1024));
+
+        send(&mut stream, 0x1, b"Hello");
+        assert_eq!(recv(&mut stream), (0x81, b"Hello".to_vec()));
+
+        // A message split across fragments, with a ping in the middle
+        stream.write_all(&[0x02, 0x81, 0, 0, 0, 0, b'a']).unwrap();
+        send(&mut stream, 0x9, b"ping");
+        stream.write_all(&[0x80, 0x81, 0, 0, 0, 0, b'b']).unwrap();
+
+        assert_eq!(recv(&mut stream), (0x8A, b"ping".to_vec()));
+        assert_eq!(recv(&mut stream), (0x82, b"ab".to_vec()));
+
+        send(&mut stream, 0x8, &[0x03, 0xE8]);
+        assert_eq!(recv(&mut stream), (0x88, vec![0x03, 0xE8]));
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        let mut stream = connect(echo(8));
+
+        send(&mut stream, 0x2, &[0; 16]);
+        assert_eq!(recv(&mut stream), (0x88, vec![0x03, 0xF1]));
+    }
+
+    #[test]
+    fn test_invalid_close() {
+        // 1005 may only be used locally, to report that no code was received
+        let mut stream = connect(echo(1024));
+        send(&mut stream, 0x8, &[0x03, 0xED]);
+        assert_eq!(recv(&mut stream), (0x88, vec![0x03, 0xEA]));
+
+        let mut stream = connect(echo(1024));
+        send(&mut stream, 0x8, &[0x03, 0xE8, 0xFF]);
+        assert_eq!(recv(&mut stream), (0x88, vec![0x03, 0xEF]));
+    }
+
+    #[test]
+    fn test_invalid_handshake() {
+        let client = TestClient::new(WebSocket::new(|_: Context, socket: Socket| {
+            socket.send("unreachable".into()).map(|_| ())
+        }));
+
+        client.get("/").send().assert_status(StatusCode::UpgradeRequired);
+        client
+            .request(Method::POST, "/")
+            .send()
+            .assert_status(StatusCode::MethodNotAllowed);
+    }
+}